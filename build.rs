@@ -0,0 +1,74 @@
+// build.rs embeds information about the build into our binaries, which is read back in lib::build.
+use std::{
+    env,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    // The commit can be given with GIT_COMMIT when building without the .git directory, for example in docker.
+    let git_commit = match env::var("GIT_COMMIT") {
+        Ok(commit) if !commit.is_empty() => commit,
+        _ => run("git", &["rev-parse", "HEAD"]).unwrap_or_else(|| String::from("unknown")),
+    };
+
+    // A build from a tree with uncommitted changes is marked, so it is not mistaken for the commit itself. This is
+    // only accurate for a clean build, like in CI or docker. Cargo only reruns this script when the files watched
+    // below change, and editing a tracked file does not touch any of them, so an incremental build keeps the flag
+    // from whenever this script last ran.
+    let git_dirty = match run("git", &["status", "--porcelain", "--untracked-files=no"]) {
+        Some(status) => !status.is_empty(),
+        None => false,
+    };
+
+    // SOURCE_DATE_EPOCH is honoured so builds can be reproducible.
+    let build_timestamp = match env::var("SOURCE_DATE_EPOCH").map(|epoch| epoch.parse::<u64>()) {
+        Ok(Ok(epoch)) => epoch,
+        _ => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default(),
+    };
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let rustc_version = run(&rustc, &["--version"]).unwrap_or_else(|| String::from("unknown"));
+
+    // Cargo passes every enabled feature as CARGO_FEATURE_<NAME>.
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=BUILD_GIT_COMMIT={}", git_commit);
+    println!("cargo:rustc-env=BUILD_GIT_DIRTY={}", git_dirty);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp);
+    println!("cargo:rustc-env=BUILD_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+    println!(
+        "cargo:rustc-env=BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+
+    // Only rebuild the information when the commit changes, rather than on every build. Branches that git has packed
+    // are moved in .git/packed-refs rather than .git/refs/heads, for example after git gc or a fetch.
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-changed=.git/packed-refs");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+// fn run() returns the trimmed output of the command, or None when it could not be run.
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => output,
+        _ => return None,
+    };
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,271 @@
+// #[derive(Conf)] implements the Conf trait from rust_starter_pack::lib::conf::conf for a settings struct, so its
+// defaults, env var names and validation live next to the fields, rather than in main.rs.
+//
+// #[derive(Conf, Deserialize, Serialize)]
+// #[conf(prefix = "DB")]
+// pub struct DatabaseSettings {
+//     #[conf(default = "postgres", validate(non_empty))]
+//     pub host: String,
+//     #[conf(default = 5432, validate(range(min = 1, max = 65535)))]
+//     pub port: u16,
+//     #[conf(default = "postgres", env = "DB_USER")]
+//     pub username: String,
+// }
+//
+// Struct attributes:
+// - prefix = "DB" : the table in config files, and the prefix of env vars and flags (DB_PORT, --db-port).
+// - crate = "path" : the path to the rust_starter_pack crate, by default ::rust_starter_pack. Structs derived within
+//   the crate itself use crate = "crate", as it can not name itself.
+//
+// Field attributes:
+// - default = <expr> : the default value, a string literal is converted with From, so it works for String and
+//   Secret<String>. Fields without a default use Default::default().
+// - env = "NAME" : the env var to read instead of PREFIX_FIELD, the flag follows the same name (--name).
+// - validate(...) : any of non_empty, range(min = 1, max = 10), one_of("a", "b"), or custom = "path::to::fn",
+//   where the function takes a reference to the field and returns Result<(), String>.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parenthesized, parse_macro_input, punctuated::Punctuated, spanned::Spanned, Data, DeriveInput,
+    Expr, ExprLit, Fields, Lit, LitStr, Path, Token,
+};
+
+#[proc_macro_derive(Conf, attributes(conf))]
+pub fn derive_conf(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct Field {
+    ident: syn::Ident,
+    default: Option<Expr>,
+    env: Option<LitStr>,
+    rules: Vec<Rule>,
+}
+
+enum Rule {
+    NonEmpty,
+    Range(Option<Box<Expr>>, Option<Box<Expr>>),
+    OneOf(Vec<LitStr>),
+    Custom(Path),
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+
+    let mut prefix = LitStr::new("", name.span());
+    let mut krate: Path = syn::parse_quote!(::rust_starter_pack);
+
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("conf"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("prefix") {
+                prefix = meta.value()?.parse()?;
+                return Ok(());
+            }
+
+            if meta.path.is_ident("crate") {
+                let path: LitStr = meta.value()?.parse()?;
+                krate = path.parse()?;
+                return Ok(());
+            }
+
+            Err(meta.error("expected prefix = \"...\" or crate = \"...\""))
+        })?;
+    }
+
+    let named = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "Conf needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "Conf can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut fields = vec![];
+
+    for field in named {
+        let mut parsed = Field {
+            ident: field.ident.clone().expect("named fields have an ident"),
+            default: None,
+            env: None,
+            rules: vec![],
+        };
+
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("conf"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("default") {
+                    parsed.default = Some(meta.value()?.parse()?);
+                    return Ok(());
+                }
+
+                if meta.path.is_ident("env") {
+                    parsed.env = Some(meta.value()?.parse()?);
+                    return Ok(());
+                }
+
+                if meta.path.is_ident("validate") {
+                    return meta.parse_nested_meta(|rule| {
+                        if rule.path.is_ident("non_empty") {
+                            parsed.rules.push(Rule::NonEmpty);
+                            return Ok(());
+                        }
+
+                        if rule.path.is_ident("range") {
+                            let (mut min, mut max) = (None, None);
+                            rule.parse_nested_meta(|bound| {
+                                if bound.path.is_ident("min") {
+                                    min = Some(bound.value()?.parse()?);
+                                    return Ok(());
+                                }
+                                if bound.path.is_ident("max") {
+                                    max = Some(bound.value()?.parse()?);
+                                    return Ok(());
+                                }
+                                Err(bound.error("expected min or max"))
+                            })?;
+                            parsed.rules.push(Rule::Range(min, max));
+                            return Ok(());
+                        }
+
+                        if rule.path.is_ident("one_of") {
+                            let content;
+                            parenthesized!(content in rule.input);
+                            let values =
+                                Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?;
+                            parsed.rules.push(Rule::OneOf(values.into_iter().collect()));
+                            return Ok(());
+                        }
+
+                        if rule.path.is_ident("custom") {
+                            let path: LitStr = rule.value()?.parse()?;
+                            parsed.rules.push(Rule::Custom(path.parse()?));
+                            return Ok(());
+                        }
+
+                        Err(rule.error("expected non_empty, range, one_of or custom"))
+                    });
+                }
+
+                Err(meta.error("expected default, env or validate"))
+            })?;
+        }
+
+        fields.push(parsed);
+    }
+
+    let conf = quote!(#krate::lib::conf::conf);
+
+    let defaults = fields.iter().map(|field| {
+        let ident = &field.ident;
+        match &field.default {
+            // String literals are converted, so "text" works for String, and Secret<String>.
+            Some(Expr::Lit(ExprLit {
+                lit: Lit::Str(text),
+                ..
+            })) => {
+                quote!(#ident: ::std::convert::From::from(#text))
+            }
+            Some(default) => quote!(#ident: #default),
+            None => quote!(#ident: ::std::default::Default::default()),
+        }
+    });
+
+    let env_names = fields.iter().filter_map(|field| {
+        let ident = field.ident.to_string();
+        field.env.as_ref().map(|env| quote!((#ident, #env)))
+    });
+
+    let mut checks = vec![];
+
+    for field in &fields {
+        let ident = &field.ident;
+        let key = ident.to_string();
+
+        for rule in &field.rules {
+            checks.push(match rule {
+                Rule::NonEmpty => quote! {
+                    if #conf::IsEmpty::is_empty(&self.#ident) {
+                        invalid.push(#conf::Invalid::new(#key, "must not be empty"));
+                    }
+                },
+                Rule::Range(min, max) => {
+                    let min_check = min.as_ref().map(|min| {
+                        quote! {
+                            if self.#ident < #min {
+                                invalid.push(#conf::Invalid::new(#key, format!("must be at least {}", #min)));
+                            }
+                        }
+                    });
+                    let max_check = max.as_ref().map(|max| {
+                        quote! {
+                            if self.#ident > #max {
+                                invalid.push(#conf::Invalid::new(#key, format!("must be at most {}", #max)));
+                            }
+                        }
+                    });
+                    quote!(#min_check #max_check)
+                }
+                Rule::OneOf(values) => quote! {
+                    if ![#(#values),*].contains(&self.#ident.as_str()) {
+                        invalid.push(#conf::Invalid::new(
+                            #key,
+                            format!("must be one of {}", [#(#values),*].join(", ")),
+                        ));
+                    }
+                },
+                Rule::Custom(path) => quote! {
+                    if let Err(reason) = #path(&self.#ident) {
+                        invalid.push(#conf::Invalid::new(#key, reason));
+                    }
+                },
+            });
+        }
+    }
+
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #conf::Conf for #name #type_generics #where_clause {
+            const PREFIX: &'static str = #prefix;
+
+            fn defaults() -> Self {
+                #name {
+                    #(#defaults,)*
+                }
+            }
+
+            fn env_names() -> &'static [(&'static str, &'static str)] {
+                &[#(#env_names),*]
+            }
+
+            fn validate(&self) -> ::std::vec::Vec<#conf::Invalid> {
+                let mut invalid = ::std::vec::Vec::new();
+                #(#checks)*
+                invalid
+            }
+        }
+    })
+}
@@ -0,0 +1,5 @@
+-- modify "users" table
+ALTER TABLE "public"."users" ADD COLUMN "password_hash" character varying NULL;
+
+-- create "sessions" table
+CREATE TABLE "public"."sessions" ("id" character varying(64) NOT NULL, "user_id" integer NOT NULL, "csrf_token" character varying(64) NOT NULL, "created_at" timestamp NOT NULL DEFAULT now(), "last_seen_at" timestamp NOT NULL DEFAULT now(), "expires_at" timestamp NOT NULL, PRIMARY KEY ("id"), CONSTRAINT "sessions_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "public"."users" ("id") ON UPDATE NO ACTION ON DELETE CASCADE);
@@ -0,0 +1,5 @@
+-- modify "users" table
+ALTER TABLE "public"."users" ADD COLUMN "mfa_secret" character varying(64) NULL, ADD COLUMN "mfa_enabled" boolean NOT NULL DEFAULT false, ADD COLUMN "mfa_last_step" bigint NULL;
+
+-- create "recovery_codes" table
+CREATE TABLE "public"."recovery_codes" ("id" serial NOT NULL, "user_id" integer NOT NULL, "code_hash" character varying(64) NOT NULL, "used_at" timestamp NULL, "created_at" timestamp NOT NULL DEFAULT now(), PRIMARY KEY ("id"), CONSTRAINT "recovery_codes_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "public"."users" ("id") ON UPDATE NO ACTION ON DELETE CASCADE);
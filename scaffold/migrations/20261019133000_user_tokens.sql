@@ -0,0 +1,5 @@
+-- modify "users" table
+ALTER TABLE "public"."users" ADD COLUMN "email_verified_at" timestamp NULL;
+
+-- create "user_tokens" table
+CREATE TABLE "public"."user_tokens" ("jti" character varying(64) NOT NULL, "user_id" integer NOT NULL, "purpose" character varying(32) NOT NULL, "expires_at" timestamp NOT NULL, "used_at" timestamp NULL, "created_at" timestamp NOT NULL DEFAULT now(), PRIMARY KEY ("jti"), CONSTRAINT "user_tokens_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "public"."users" ("id") ON UPDATE NO ACTION ON DELETE CASCADE);
@@ -94,6 +94,114 @@ table "audit_logs" {
     columns = [column.id]
   }
 }
+table "recovery_codes" {
+  schema = schema.public
+  column "id" {
+    null = false
+    type = serial
+  }
+  column "user_id" {
+    null = false
+    type = integer
+  }
+  column "code_hash" {
+    null = false
+    type = character_varying(64)
+  }
+  column "used_at" {
+    null = true
+    type = timestamp
+  }
+  column "created_at" {
+    null    = false
+    type    = timestamp
+    default = sql("now()")
+  }
+  primary_key {
+    columns = [column.id]
+  }
+  foreign_key "recovery_codes_user_id_fkey" {
+    columns     = [column.user_id]
+    ref_columns = [table.users.column.id]
+    on_update   = NO_ACTION
+    on_delete   = CASCADE
+  }
+}
+table "sessions" {
+  schema = schema.public
+  column "id" {
+    null = false
+    type = character_varying(64)
+  }
+  column "user_id" {
+    null = false
+    type = integer
+  }
+  column "csrf_token" {
+    null = false
+    type = character_varying(64)
+  }
+  column "created_at" {
+    null    = false
+    type    = timestamp
+    default = sql("now()")
+  }
+  column "last_seen_at" {
+    null    = false
+    type    = timestamp
+    default = sql("now()")
+  }
+  column "expires_at" {
+    null = false
+    type = timestamp
+  }
+  primary_key {
+    columns = [column.id]
+  }
+  foreign_key "sessions_user_id_fkey" {
+    columns     = [column.user_id]
+    ref_columns = [table.users.column.id]
+    on_update   = NO_ACTION
+    on_delete   = CASCADE
+  }
+}
+table "user_tokens" {
+  schema = schema.public
+  column "jti" {
+    null = false
+    type = character_varying(64)
+  }
+  column "user_id" {
+    null = false
+    type = integer
+  }
+  column "purpose" {
+    null = false
+    type = character_varying(32)
+  }
+  column "expires_at" {
+    null = false
+    type = timestamp
+  }
+  column "used_at" {
+    null = true
+    type = timestamp
+  }
+  column "created_at" {
+    null    = false
+    type    = timestamp
+    default = sql("now()")
+  }
+  primary_key {
+    columns = [column.jti]
+  }
+  foreign_key "user_tokens_user_id_fkey" {
+    columns     = [column.user_id]
+    ref_columns = [table.users.column.id]
+    on_update   = NO_ACTION
+    on_delete   = CASCADE
+  }
+}
 table "users" {
   schema = schema.public
   column "id" {
@@ -125,6 +233,27 @@ table "users" {
     null = true
     type = timestamp
   }
+  column "password_hash" {
+    null = true
+    type = character_varying
+  }
+  column "mfa_secret" {
+    null = true
+    type = character_varying(64)
+  }
+  column "mfa_enabled" {
+    null    = false
+    type    = boolean
+    default = false
+  }
+  column "mfa_last_step" {
+    null = true
+    type = bigint
+  }
+  column "email_verified_at" {
+    null = true
+    type = timestamp
+  }
   primary_key {
     columns = [column.id]
   }
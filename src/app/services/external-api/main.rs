@@ -1,27 +1,71 @@
 mod config;
 mod mux;
 
-use crate::config::Conf;
 use mux::mux as axum_mux;
+use rust_starter_pack::domain::system::auth::account::AccountConfig;
 use rust_starter_pack::domain::system::auth::auth::AuthConfig;
+use rust_starter_pack::domain::system::auth::auth::KeyRing;
+use rust_starter_pack::domain::system::auth::auth::LoginConfig;
+use rust_starter_pack::domain::system::auth::mfa::MfaConfig;
+use rust_starter_pack::domain::system::auth::password;
+use rust_starter_pack::domain::system::auth::session::SessionConfig;
+use rust_starter_pack::domain::web::middleware::cors::CorsConfig;
+use rust_starter_pack::lib::build::build;
+use rust_starter_pack::lib::conf::{conf, reload};
+use rust_starter_pack::lib::features::features;
+use rust_starter_pack::lib::health::health;
+use rust_starter_pack::lib::limiter::limiter;
 use rust_starter_pack::lib::logger::logger;
+use rust_starter_pack::lib::mailer::mailer;
+use rust_starter_pack::lib::server::registry::{self, Registry};
+use rust_starter_pack::lib::server::{limits, tls};
 use rust_starter_pack::{domain::system::auth::auth, lib::database::database};
 use serde::Serialize;
 use signal_hook::consts::SIGTERM;
 use signal_hook::{consts::SIGINT, iterator::Signals};
+use sqlx::postgres::PgSslMode;
+use std::collections::BTreeSet;
 use std::io::Error;
-use tokio::sync::oneshot;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{env, path::PathBuf};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
 
-// The main config struct, this contains your derived structs that can be mapped from a .env.
-// Defaults of these can also be provided when initialising the struct in fn start_up()
+// The main config struct, this contains your derived structs that can be mapped from config files, a .env, and flags.
 // All of your custom configurations should be applied in config.rs, derive Serializable, and
-// Impl Conf in order to allow .env mappings and defaults.
+// derive Conf in order to allow config mappings, defaults, and validation.
 #[derive(Serialize)]
 pub struct AppConfig {
     pub app: config::AppSettings,
     pub web: config::WebSettings,
     pub db: config::DatabaseSettings,
+    pub db_replica: config::ReplicaSettings,
     pub auth: config::AuthSettings,
+    pub session: config::SessionSettings,
+    pub mail: config::MailSettings,
+    pub health: config::HealthSettings,
+    pub cors: config::CorsSettings,
+}
+
+// Running contains everything that must be stopped, in order, when the application shuts down.
+pub struct Running {
+    // Set when a server stopped, or the database could not be connected to, so the application exits with an error
+    // once shut down.
+    pub failure: Option<Box<dyn std::error::Error + Send + Sync>>,
+    // Sending true tells both servers to stop accepting new connections.
+    pub shutdown: watch::Sender<bool>,
+    pub servers: Vec<JoinHandle<()>>,
+    // Reports readiness as failing once shutdown begins.
+    pub registry: Registry,
+    // How long readiness fails before the servers stop.
+    pub shutdown_delay: Duration,
+    // How long in-flight requests are given to finish.
+    pub shutdown_timeout: Duration,
+    pub mailer: mailer::Mailer,
+    pub db: database::Database,
 }
 
 /// main.rs acts as the entrypoint for our start up and shutdown for this executable.
@@ -32,6 +76,7 @@ async fn main() {
     // Logger configuration to allow this application to define our custom logger.
     let logger_config = logger::Config {
         name: String::from("external-api"),
+        version: build::version(),
         max_log_level: log::LevelFilter::Debug,
     };
 
@@ -43,23 +88,12 @@ async fn main() {
     // We now begin the start up function in order to bundle our modules, and setup
     // our services ready to listen to events. We bubble any errors up during our start up
     // sequence in order for them to be handled for our shutdown function.
-    if let Err(err) = start_up(&log).await {
-        // Shut down process to attempt graceful shutdown of our application.
-        log.error_w(
-            format!(
-                "error during start up sequence, shutting down application gracefully. Error : {}",
-                err.to_string()
-            )
-            .as_str(),
-            Some("RUST WEB API MAIN"),
-        );
-
-        // Shut down process to attempt graceful shutdown of our application.
-        // therefore we can use a callack to log the error, and start the shutdown process.
-        if let Err(err) = shut_down(&log, err) {
+    let mut running = match start_up(&log).await {
+        Ok(running) => running,
+        Err(err) => {
             log.error_w(
                 format!(
-                    "error during shutdown process, exiting application. Error : {}",
+                    "error during start up sequence, exiting application. Error : {}",
                     err.to_string()
                 )
                 .as_str(),
@@ -68,93 +102,430 @@ async fn main() {
             std::process::exit(1);
         }
     };
+
+    // Shut down process to attempt graceful shutdown of our application, once a signal has been received.
+    let failure = running.failure.take();
+
+    if let Err(err) = shut_down(&log, running).await {
+        log.error_w(
+            format!(
+                "error during shutdown process, exiting application. Error : {}",
+                err.to_string()
+            )
+            .as_str(),
+            Some("RUST WEB API MAIN"),
+        );
+        std::process::exit(1);
+    }
+
+    // The application was shut down because a server, or the database connection failed, so we exit with the real
+    // cause.
+    if let Some(err) = failure {
+        log.error_w(
+            format!("stopped unexpectedly, exiting application. Error : {}", err).as_str(),
+            Some("RUST WEB API MAIN"),
+        );
+        std::process::exit(1);
+    }
+}
+
+// fn config_problems() returns the report of every config problem as the start up error. With --check-config, the
+// report is printed as is instead, rather than logged as JSON, as it is read by a person.
+fn config_problems(report: conf::Report, check_config: bool) -> Box<dyn std::error::Error> {
+    if check_config {
+        eprintln!("{}", report);
+        std::process::exit(1);
+    }
+
+    report.into()
+}
+
+// Publishers send each component the reloadable settings it uses.
+struct Publishers {
+    features: watch::Sender<BTreeSet<String>>,
+    mail_limits: watch::Sender<limiter::Config>,
+    cors: watch::Sender<CorsConfig>,
+    keys: watch::Sender<KeyRing>,
+}
+
+// fn apply_reloads() sends every reload of the settings on to the components that use them, components are only
+// woken when their own settings changed.
+async fn apply_reloads(
+    mut settings: watch::Receiver<Arc<config::Reloadable>>,
+    logger: logger::Logger,
+    publishers: Publishers,
+) {
+    while settings.changed().await.is_ok() {
+        let reloaded = settings.borrow().clone();
+
+        apply_log_level(&logger, &reloaded);
+        publish(&publishers.features, reloaded.features());
+        publish(&publishers.mail_limits, reloaded.mail_limits());
+        publish(&publishers.cors, reloaded.cors());
+        publish(&publishers.keys, reloaded.keys());
+    }
+}
+
+fn publish<T: PartialEq>(sender: &watch::Sender<T>, value: T) {
+    sender.send_if_modified(|current| {
+        if *current == value {
+            return false;
+        }
+        *current = value;
+        true
+    });
+}
+
+// fn apply_log_level() applies the configured log levels, an empty log level leaves the current levels as they are.
+fn apply_log_level(logger: &logger::Logger, settings: &config::Reloadable) {
+    if settings.log_level.is_empty() {
+        return;
+    }
+
+    if let Err(err) = logger.levels().apply_directives(&settings.log_level) {
+        logger.warn_w(
+            format!("could not apply the log level : {}", err).as_str(),
+            Some("Rust API startup"),
+        );
+    }
 }
 
 // fn start_up() performs all related start up configuration to load our service,
 // this is where you will initialise your modules to then be used within your application.
-async fn start_up(logger: &logger::Logger) -> Result<(), Box<dyn std::error::Error>> {
+// Once a shutdown signal has been received, everything that needs to be stopped is returned.
+async fn start_up(logger: &logger::Logger) -> Result<Running, Box<dyn std::error::Error>> {
+    // Used to report how long the service has been running.
+    let started = SystemTime::now();
+
     // ---------------------------------------
     // start up configuration.
 
-    // Define default application configuration.
-    let default_config = AppConfig {
-        // Default configuration can be added like so.
-        app: config::AppSettings {
-            version: String::from("0.0.1"),
-            environment: String::from("development"),
-        }
-        .load_from_env(&logger, "")?, // And then we can override from env if needed.
-        web: config::WebSettings {
-            address: String::from("0.0.0.0"),
-            port: 80,
-            debug_address: String::from("0.0.0.0"),
-            debug_port: 4080,
-        }
-        .load_from_env(&logger, "WEB")?,
-        db: config::DatabaseSettings {
-            host: String::from("postgres"),
-            port: 5432,
-            username: String::from("postgres"),
-            password: String::from("example"),
-            schema: String::from("postgres"),
-        }
-        .load_from_env(&logger, "DB")?,
-        auth: config::AuthSettings {
-            enabled: false,
-            key_id: String::from("some-uuid"),
-            public_key: String::from("******"),
-        }
-        .load_from_env(&logger, "AUTH")?,
+    // Settings are layered, defaults below < config/base.toml < config/{environment}.toml < env vars < flags.
+    let layers_config = conf::Config {
+        args: env::args().skip(1).collect(),
+        directory: PathBuf::from("config"),
+        default_environment: String::from("development"),
     };
 
+    let layers = conf::new(layers_config.clone())?;
+
+    for warning in layers.warnings() {
+        logger.warn_w(warning, Some("Rust Web API Start Up"));
+    }
+
+    // Read before checking for unknown flags, as it is not a setting.
+    let check_config = layers.flag("check-config").is_some();
+
+    // Load our application configuration, the defaults of each setting are in config.rs. Every problem is collected
+    // before giving up, so they can all be fixed at once.
+    let mut report = conf::Report::new();
+
+    let loaded = (
+        layers.check::<config::AppSettings>(&mut report),
+        layers.check::<config::WebSettings>(&mut report),
+        layers.check::<config::DatabaseSettings>(&mut report),
+        layers.check::<config::ReplicaSettings>(&mut report),
+        layers.check::<config::AuthSettings>(&mut report),
+        layers.check::<config::SessionSettings>(&mut report),
+        layers.check::<config::MailSettings>(&mut report),
+        layers.check::<config::HealthSettings>(&mut report),
+        layers.check::<config::CorsSettings>(&mut report),
+    );
+
+    // Any flag that did not set a setting is most likely mistyped.
+    for flag in layers.unused_flags() {
+        report.push(conf::Error {
+            key: flag.clone(),
+            source: conf::Source::Flag(flag),
+            reason: String::from("unknown flag"),
+        });
+    }
+
+    let default_config = match loaded {
+        (
+            Some(app),
+            Some(web),
+            Some(db),
+            Some(db_replica),
+            Some(auth),
+            Some(session),
+            Some(mail),
+            Some(health),
+            Some(cors),
+        ) => AppConfig {
+            app,
+            web,
+            db,
+            db_replica,
+            auth,
+            session,
+            mail,
+            health,
+            cors,
+        },
+        _ => return Err(config_problems(report, check_config)),
+    };
+
+    for problem in config::validate(&default_config, &layers) {
+        report.push(problem);
+    }
+
+    if !report.is_empty() {
+        return Err(config_problems(report, check_config));
+    }
+
+    // --check-config only validates the config, so it can be checked before a deploy.
+    if check_config {
+        logger.info_w(
+            format!("config is valid for environment {}", layers.environment()).as_str(),
+            Some("Rust API startup"),
+        );
+        std::process::exit(0);
+    }
+
+    let config_sources = layers.sources();
+
+    logger.info_w(
+        format!(
+            "config loaded for environment {} from files {:?}",
+            layers.environment(),
+            layers.files()
+        )
+        .as_str(),
+        Some("Rust API startup"),
+    );
+
     // -----------------------------------------------------------
-    // Log default configuration
-    match serde_json::to_string(&default_config) {
-        Ok(json) => {
-            logger.info_w(json.as_str(), Some("Rust API startup"));
-        }
-        Err(err) => logger.warn_w(
+    // Log default configuration, credentials are wrapped in a Secret so they are never logged, and the same config
+    // is shown at /debug/info. Anything that looks like a credential but is not wrapped is warned about.
+    for setting in config::unwrapped_secrets(&default_config) {
+        logger.warn_w(
             format!(
-                "could not serialise default config, skipping.. : {}",
-                err.to_string()
+                "setting {} looks like a credential, but is not wrapped in a Secret, so it will be logged",
+                setting
             )
             .as_str(),
             Some("Rust API startup"),
-        ),
+        );
     }
 
+    let effective_config = match serde_json::to_value(&default_config) {
+        Ok(json) => {
+            logger.info_w(json.to_string().as_str(), Some("Rust API startup"));
+            json
+        }
+        Err(err) => {
+            logger.warn_w(
+                format!(
+                    "could not serialise default config, skipping.. : {}",
+                    err.to_string()
+                )
+                .as_str(),
+                Some("Rust API startup"),
+            );
+            serde_json::Value::Null
+        }
+    };
+
+    // -----------------------------------------------------------
+    // Reloadable settings, these are re-read on SIGHUP, or when a config file changes, and each component that uses
+    // them is sent the new settings through its own watch channel.
+    let reloadable = config::Reloadable::new(
+        &default_config.app,
+        &default_config.mail,
+        &default_config.cors,
+        &default_config.auth,
+    );
+
+    apply_log_level(logger, &reloadable);
+
+    let (features_send, features_recv) = watch::channel(reloadable.features());
+    let (mail_limits_send, mail_limits_recv) = watch::channel(reloadable.mail_limits());
+    let (cors_send, cors_recv) = watch::channel(reloadable.cors());
+    let (keys_send, keys_recv) = watch::channel(reloadable.keys());
+
+    let reloader = reload::new(
+        reload::Config {
+            layers: layers_config,
+            interval: Duration::from_secs(default_config.app.reload_interval),
+            load: config::load_reloadable,
+            logger: logger.clone(),
+        },
+        reloadable,
+        layers.files(),
+    );
+
+    tokio::spawn(apply_reloads(
+        reloader.subscribe(),
+        logger.clone(),
+        Publishers {
+            features: features_send,
+            mail_limits: mail_limits_send,
+            cors: cors_send,
+            keys: keys_send,
+        },
+    ));
+
     // -----------------------------------------------------------
     // Custom postgres configuration, and initialsation.
+    let ssl_mode = match PgSslMode::from_str(&default_config.db.ssl_mode) {
+        Ok(ssl_mode) => ssl_mode,
+        Err(err) => return Err(err.into()),
+    };
+
     let database_config = database::Config {
+        url: Some(default_config.db.url.into_inner()).filter(|url| !url.is_empty()),
         db_host: default_config.db.host,
         db_port: default_config.db.port,
         db_username: default_config.db.username,
-        db_password: default_config.db.password,
+        db_password: default_config.db.password.into_inner(),
         db_schema: default_config.db.schema,
-        max_connections: 2,
-        enable_ssl: sqlx::postgres::PgSslMode::Disable,
+        ssl_mode,
+        ssl_root_cert: Some(default_config.db.ssl_root_cert).filter(|path| !path.is_empty()),
+        application_name: default_config.db.application_name,
+        statement_timeout: Some(Duration::from_millis(default_config.db.statement_timeout))
+            .filter(|timeout| !timeout.is_zero()),
+        min_connections: default_config.db.min_connections,
+        max_connections: default_config.db.max_connections,
+        acquire_timeout: Duration::from_secs(default_config.db.acquire_timeout),
+        idle_timeout: Some(Duration::from_secs(default_config.db.idle_timeout))
+            .filter(|timeout| !timeout.is_zero()),
+        max_lifetime: Some(Duration::from_secs(default_config.db.max_lifetime))
+            .filter(|timeout| !timeout.is_zero()),
+    };
+
+    // The replica connects as the primary does, apart from where to, and how many connections it can open.
+    let replica_config = match default_config.db_replica.enabled() {
+        true => Some(database::Config {
+            url: Some(default_config.db_replica.url.into_inner()).filter(|url| !url.is_empty()),
+            db_host: default_config.db_replica.host,
+            db_port: default_config.db_replica.port,
+            min_connections: database_config
+                .min_connections
+                .min(default_config.db_replica.max_connections),
+            max_connections: default_config.db_replica.max_connections,
+            ..database_config.clone()
+        }),
+        false => None,
+    };
+
+    let retry = database::Retry {
+        backoff: Duration::from_millis(default_config.db.connect_backoff),
+        max_backoff: Duration::from_millis(default_config.db.connect_max_backoff),
+        deadline: Duration::from_secs(default_config.db.connect_deadline),
     };
 
-    let db = match database::open_postgres_database(database_config).await {
+    // The pool is created without connecting, we then connect with retries, as postgres may still be starting.
+    let primary = match database::open_lazy_postgres_database(database_config.clone()) {
         Ok(db) => db,
         Err(err) => {
             return Err(err)?;
         }
     };
 
-    logger.info_w("postgres database loaded", Some("Rust Web API Start Up"));
+    // Sent to when connecting in the background gives up, so the application shuts down.
+    let (db_send, db_recv) = oneshot::channel();
+
+    if default_config.db.start_degraded {
+        // Readiness fails until connected, as the database check can not pass before then.
+        logger.warn_w(
+            "starting degraded, the service is not ready until the database is connected",
+            Some("Rust Web API Start Up"),
+        );
+
+        let logger = logger.clone();
+
+        tokio::spawn(async move {
+            let connected = database::connect_with_retry(&database_config, &retry, &logger).await;
+
+            if let Err(err) = connected {
+                db_send.send(err).ok();
+            }
+        });
+    } else {
+        if let Err(err) = database::connect_with_retry(&database_config, &retry, logger).await {
+            return Err(err)?;
+        }
+
+        logger.info_w("postgres database loaded", Some("Rust Web API Start Up"));
+    }
+
+    // The replica is connected to lazily, so the service starts while it is down, and reads use the primary until
+    // it answers.
+    let replica = match replica_config {
+        Some(replica_config) => match database::open_lazy_postgres_database(replica_config) {
+            Ok(db) => {
+                logger.info_w("postgres replica loaded", Some("Rust Web API Start Up"));
+                Some(db)
+            }
+            Err(err) => {
+                return Err(err)?;
+            }
+        },
+        None => None,
+    };
+
+    let db = database::new(
+        primary,
+        replica,
+        database::Instrumentation {
+            logger: logger.clone(),
+            slow_query: Some(Duration::from_millis(default_config.db.slow_query))
+                .filter(|slow_query| !slow_query.is_zero()),
+        },
+    );
+
+    // -----------------------------------------------------------
+    // Mail support, used to send verification and password reset emails.
+    let mailer_config = mailer::Config {
+        transport: default_config.mail.transport,
+        from: default_config.mail.from,
+        smtp_host: default_config.mail.smtp_host,
+        smtp_port: default_config.mail.smtp_port,
+        smtp_username: default_config.mail.smtp_username,
+        smtp_password: default_config.mail.smtp_password.into_inner(),
+        directory: default_config.mail.directory,
+        logger: logger.clone(),
+    };
+
+    let mailer = match mailer::new(mailer_config) {
+        Ok(mailer) => mailer,
+        Err(err) => return Err(err),
+    };
+
+    logger.info_w("mailer loaded", Some("Rust Web API Start Up"));
 
     // -----------------------------------------------------------
     // Auth support
     let auth_config = AuthConfig {
         enabled: default_config.auth.enabled,
-        key_id: default_config.auth.key_id,
+        keys: keys_recv,
         signing_method: jsonwebtoken::Algorithm::RS256,
         db: db.clone(),
+        session: SessionConfig {
+            enabled: default_config.session.enabled,
+            idle_timeout: Duration::from_secs(default_config.session.idle_timeout),
+            absolute_timeout: Duration::from_secs(default_config.session.absolute_timeout),
+            secure: default_config.session.secure_cookies,
+            same_site: default_config.session.same_site,
+        },
+        account: AccountConfig {
+            mailer: mailer.clone(),
+            limiter: limiter::watched(mail_limits_recv),
+            link_url: default_config.mail.link_url,
+            ..AccountConfig::default()
+        },
+        mfa: MfaConfig::default(),
+        login: LoginConfig::default(),
     };
 
     let auth = auth::new(auth_config);
 
+    // Unknown emails are checked against a dummy hash, which must exist before the first login.
+    if let Err(err) = password::init_dummy_hash() {
+        return Err(err.message.into());
+    }
+
     logger.info_w("auth config loaded", Some("Rust Web API Start Up"));
 
     // Now all custom modules have been loaded, we can now start creating threads for our web server, signals, and any other
@@ -163,10 +534,10 @@ async fn start_up(logger: &logger::Logger) -> Result<(), Box<dyn std::error::Err
     // Firstly we will create a one time signal and thread that sends a signal to the receiver upon a SIGINT OR SIGTERM event.
     let (signal_send, signal_receive) = oneshot::channel();
 
-    // This is where we pass in our signals into a new thread. This thread simply loops over the signal forever, until one
-    // of SIGTERM or SIGINT signal has been matched. Because we are using a loop here, we need to let the borrow checker
+    // This is where we pass in our signals into a new thread, outside of the runtime so it never holds it up, nor
+    // keeps it from shutting down. This thread simply loops over the signal forever, until one of SIGTERM or SIGINT signal has been matched. Because we are using a loop here, we need to let the borrow checker
     // know that signal_send is a Option, and if we can take from it, we send a signal back to the receiver.
-    tokio::spawn(async move {
+    std::thread::spawn(move || {
         let mut signal_send = Some(signal_send);
         let mut signal_interupt = Signals::new(&[SIGINT, SIGTERM]).unwrap();
         for signal in signal_interupt.forever() {
@@ -188,19 +559,88 @@ async fn start_up(logger: &logger::Logger) -> Result<(), Box<dyn std::error::Err
         }
     });
 
-    // Finally, we can set up our web and debug server, we also create a onetime channel for graceful shutdowns.
+    // Finally, we can set up our web and debug server, we also create a onetime channel for when a server exits
+    // unexpectedly, and a watch channel to tell both servers to shutdown gracefully.
     let (web_send, web_recv) = oneshot::channel();
     let (debug_send, debug_recv) = oneshot::channel();
+    let (shutdown_send, shutdown_recv) = watch::channel(false);
+
+    // The reloadable settings are watched until shutdown.
+    tokio::spawn(reloader.clone().watch(shutdown_recv.clone()));
+
+    // Reads move between the replica and the primary as the replica becomes unhealthy, and recovers.
+    tokio::spawn(db.clone().watch_replica(
+        Duration::from_secs(default_config.db_replica.check_interval),
+        logger.clone(),
+        shutdown_recv.clone(),
+    ));
+
+    // The socket mode is given in octal, like chmod.
+    let socket_mode = match default_config.web.socket_mode.as_str() {
+        "" => None,
+        mode => match u32::from_str_radix(mode, 8) {
+            Ok(mode) => Some(mode),
+            Err(err) => return Err(format!("invalid socket mode {} : {}", mode, err).into()),
+        },
+    };
+
+    // TLS is only enabled once both a certificate and key have been configured.
+    let tls_config = match (
+        default_config.web.tls_cert_path.is_empty(),
+        default_config.web.tls_key_path.is_empty(),
+    ) {
+        (false, false) => Some(tls::Config {
+            cert_path: default_config.web.tls_cert_path,
+            key_path: default_config.web.tls_key_path,
+            client_ca_path: Some(default_config.web.tls_client_ca_path)
+                .filter(|path| !path.is_empty()),
+            client_auth_required: default_config.web.tls_client_auth_required,
+            redirect_port: Some(default_config.web.tls_redirect_port).filter(|port| *port != 0),
+            reload_interval: Duration::from_secs(default_config.web.tls_reload_interval),
+            logger: logger.clone(),
+        }),
+        _ => None,
+    };
+
+    // Components register their checks with the registry, which the debug server serves as /livez, /readyz and
+    // /startupz.
+    let health_registry = registry::new();
 
     let handler_config = axum_mux::MuxConfig {
         environment: default_config.app.environment,
+        started,
+        effective_config,
+        config_sources,
         web_address: default_config.web.address,
         web_port: default_config.web.port,
         debug_address: default_config.web.debug_address,
         debug_port: default_config.web.debug_port,
+        socket_mode,
+        tls: tls_config,
+        limits: limits::Config {
+            request_timeout: Duration::from_secs(default_config.web.request_timeout),
+            header_read_timeout: Duration::from_secs(default_config.web.header_read_timeout),
+            keep_alive: default_config.web.keep_alive,
+            max_body_size: default_config.web.max_body_size,
+            max_concurrent_requests: default_config.web.max_concurrent_requests,
+            retry_after: Duration::from_secs(default_config.web.retry_after),
+            http2_enabled: default_config.web.http2_enabled,
+            http2_only: default_config.web.http2_only,
+        },
+        health: health::Config {
+            max_attempts: default_config.health.max_attempts,
+            timeout: Duration::from_millis(default_config.health.timeout),
+            backoff: Duration::from_millis(default_config.health.backoff),
+            max_backoff: Duration::from_millis(default_config.health.max_backoff),
+        },
+        registry: health_registry.clone(),
         logger: logger,
-        db: db,
+        db: db.clone(),
         auth: auth,
+        public_routes: default_config.auth.public_routes,
+        cors: cors_recv,
+        features: features::new(features_recv),
+        reloader,
     };
 
     // Finally, we create our new app, that passes in all the relevant configurations from start up.
@@ -209,67 +649,114 @@ async fn start_up(logger: &logger::Logger) -> Result<(), Box<dyn std::error::Err
 
     // Once we run the server, this will now be ran in a seperate thread, as above, the channel we send will notifiy the below
     // select statement.
-    web_server.run_sever(web_send)?;
+    let web_handle = web_server.run_sever(shutdown_recv.clone(), web_send)?;
 
     // This will also contain a seperate debug server, serving on a different port and ofcourse thread.
-    debug_server.run_sever(debug_send)?;
+    let debug_handle = debug_server.run_sever(shutdown_recv, debug_send)?;
 
     logger.info_w("axum servers loaded", Some("Rust Web API Start Up"));
 
+    // Everything has started, so /startupz can now pass.
+    health_registry.mark_started();
+
+    let mut running = Running {
+        failure: None,
+        shutdown: shutdown_send,
+        servers: vec![web_handle, debug_handle],
+        registry: health_registry,
+        shutdown_delay: Duration::from_secs(default_config.web.shutdown_delay),
+        shutdown_timeout: Duration::from_secs(default_config.web.shutdown_timeout),
+        mailer,
+        db,
+    };
+
     // This is where we will block the main thread until one of these signals is received back. Once a signal has been sent
-    // From either, our packages, or from sigint, we then attempt to gracefully shutdown the application, if an error occurs
-    // from then, we will attempt to shutdown the program ungracefully, and then a solution to stop these should be implemented.
+    // From either, our packages, or from sigint, we then return everything that is running, so it can be shutdown gracefully.
     tokio::select! {
             val = web_recv => {
                 logger.info_w("signal received from web server, starting graceful shutdown", Some("Rust Web API Start Up"));
-                match val {
-                    Ok(_) => {
-                        return Ok(());
-                    },
-                    Err(err) => {
-                        return Err(Box::new(err));
-                    }
-                };
+                running.failure = val.ok().map(|err| err.into());
             },
             val = debug_recv => {
                 logger.info_w("signal received from debug server, starting graceful shutdown", Some("Rust Web API Start Up"));
-                match val {
-                    Ok(_) => {
-                        return Ok(());
-                    },
-                    Err(err) => {
-                        return Err(Box::new(err));
-                    }
-                };
+                running.failure = val.ok().map(|err| err.into());
             },
-            val = signal_receive => {
-              logger.info_w("signal received from sigint, starting graceful shutdown", Some("Rust Web API Start Up"));
-                match val {
-                    Ok(_) => {
-                        return Ok(());
-                    },
-                    Err(err) => {
-                        return Err(Box::new(err));
-                    }
-                };
+            Ok(err) = db_recv => {
+                logger.info_w("could not connect to the database, starting graceful shutdown", Some("Rust Web API Start Up"));
+                running.failure = Some(err.into());
+            },
+            _ = signal_receive => {
+                logger.info_w("signal received from sigint, starting graceful shutdown", Some("Rust Web API Start Up"));
             },
     };
+
+    Ok(running)
 }
 
 // fn shut_down() acts as the shutdown sequence to safely and gracefully shutdown our application.
-fn shut_down(logger: &logger::Logger, err: Box<dyn std::error::Error>) -> Result<(), Error> {
-    // We currently just log here to notify we are about to start the shut down process.
+// The servers are stopped first, so no new work arrives, then background workers are flushed, and finally the
+// database pool is closed, as the requests and workers before it may still be using it.
+async fn shut_down(logger: &logger::Logger, running: Running) -> Result<(), Error> {
     logger.info_w(
-        format!(
-            "attempting graceful shutdown of service : reason {}",
-            err.to_string()
-        )
-        .as_str(),
+        "attempting graceful shutdown of service",
         Some("Rust Web API Shut Down"),
     );
 
-    // We currently just return OK. But this is where we will make sure to stop threads, workers
-    // and clean up the application.
+    // Readiness fails first, and we wait for the delay so load balancers stop sending us new traffic.
+    running.registry.begin_shutdown();
+
+    if !running.shutdown_delay.is_zero() {
+        logger.info_w(
+            "readiness is failing, waiting before stopping the servers",
+            Some("Rust Web API Shut Down"),
+        );
+        tokio::time::sleep(running.shutdown_delay).await;
+    }
+
+    // Stop accepting new connections, and wait for in-flight requests up until the deadline.
+    running.shutdown.send(true).ok();
+
+    let deadline = Instant::now() + running.shutdown_timeout;
+
+    for server in running.servers {
+        let abort = server.abort_handle();
+
+        match timeout_at(deadline, server).await {
+            Ok(_) => {}
+            Err(_) => {
+                // The deadline has passed, so any remaining connections are dropped.
+                abort.abort();
+                logger.warn_w(
+                    "in-flight requests did not finish before the shutdown deadline, dropping connections",
+                    Some("Rust Web API Shut Down"),
+                );
+            }
+        }
+    }
+
+    logger.info_w("axum servers stopped", Some("Rust Web API Shut Down"));
+
+    // Flush any emails still being sent in the background.
+    if timeout_at(deadline, running.mailer.flush()).await.is_err() {
+        logger.warn_w(
+            "background emails did not finish before the shutdown deadline",
+            Some("Rust Web API Shut Down"),
+        );
+    }
+
+    logger.info_w("background workers flushed", Some("Rust Web API Shut Down"));
+
+    // Finally, we close the database pools, which waits for any connections to be returned. A connection that is
+    // never returned would hold up the shutdown, so this is also bound by the deadline.
+    if timeout_at(deadline, running.db.close()).await.is_err() {
+        logger.warn_w(
+            "postgres connections were not returned before the shutdown deadline, closing without them",
+            Some("Rust Web API Shut Down"),
+        );
+        return Ok(());
+    }
+
+    logger.info_w("postgres database closed", Some("Rust Web API Shut Down"));
 
     Ok(())
 }
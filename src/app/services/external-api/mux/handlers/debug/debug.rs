@@ -1,41 +1,222 @@
-use axum::{extract::State, response::IntoResponse, Json};
-use rust_starter_pack::{
-    domain::system::error::error::SystemError,
-    lib::{database::database, server::server::liveness_check},
+use crate::config::Reloadable;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use rust_starter_pack::domain::system::error::error::SystemError;
+use rust_starter_pack::lib::{
+    build::build,
+    conf::{
+        conf::Source,
+        reload::{self, Reloader},
+    },
+    database::database,
+    health::health::{self, Status},
+    logger::{
+        levels::{Change, Snapshot},
+        logger::Logger,
+    },
+    metrics::metrics,
+    server::server::liveness_check,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    process,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use sqlx::PgPool;
-use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct DebugContext {
     pub version: String,
-    pub db: PgPool,
+    pub db: database::Database,
+    // The address and port the web server was configured with, used to ping it.
     pub web_address: String,
     pub web_port: u16,
+    pub web_tls: bool,
+    pub health: health::Config,
+    pub logger: Logger,
+    pub environment: String,
+    pub started: SystemTime,
+    // The config of the service, with sensitive settings redacted.
+    pub effective_config: Value,
+    pub config_sources: BTreeMap<String, Source>,
+    pub reloader: Reloader<Reloadable>,
 }
 
-pub async fn check_database_status(
-    State(context): State<Arc<DebugContext>>,
-) -> Result<impl IntoResponse, SystemError> {
-    if let Err(err) = database::readiness_check(&context.db, 10).await {
-        return Err(SystemError::new(
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            err.to_string(),
-        ));
-    };
+// The build, and runtime information of the service.
+#[derive(Serialize)]
+pub struct DebugInfo {
+    pub build: build::Info,
+    pub environment: String,
+    pub pid: u32,
+    // Seconds since the unix epoch.
+    pub started_at: u64,
+    pub uptime_seconds: u64,
+    pub config: Value,
+    // Where each setting came from, for example "db.port": "env DB_PORT".
+    pub config_sources: BTreeMap<String, Source>,
+}
+
+// The outcome of reloading the config, and the reloadable settings currently in use.
+#[derive(Serialize)]
+pub struct DebugReload {
+    pub status: reload::Status,
+    pub settings: Arc<Reloadable>,
+}
+
+// A change to the log level. Without a module the global level is changed, and without a level the module goes back
+// to the global level. With revert_after (in seconds) the change is undone once it has passed.
+#[derive(Deserialize)]
+pub struct DebugPutLogLevel {
+    pub module: Option<String>,
+    pub level: Option<String>,
+    pub revert_after: Option<u64>,
+}
 
-    Ok(Json("database OK"))
+pub async fn check_database_status(State(context): State<Arc<DebugContext>>) -> impl IntoResponse {
+    to_response(database::readiness_check(&context.db, &context.health).await)
 }
 
 pub async fn check_web_server_status(
     State(context): State<Arc<DebugContext>>,
-) -> Result<impl IntoResponse, SystemError> {
-    if let Err(err) = liveness_check(context.web_address.clone(), context.web_port, 10).await {
-        return Err(SystemError::new(
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            err.to_string(),
-        ));
+) -> impl IntoResponse {
+    to_response(
+        liveness_check(
+            &context.web_address,
+            context.web_port,
+            context.web_tls,
+            &context.health,
+        )
+        .await,
+    )
+}
+
+// fn get_metrics() returns every metric in the Prometheus text format.
+pub async fn get_metrics(State(context): State<Arc<DebugContext>>) -> impl IntoResponse {
+    let metrics = metrics::global();
+    metrics.observe_pool(context.db.primary());
+
+    match metrics.render() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+            body,
+        )
+            .into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not render metrics : {}", err),
+        )
+            .into_response(),
+    }
+}
+
+// fn get_info() returns which build is running, for how long, and with which config.
+pub async fn get_info(State(context): State<Arc<DebugContext>>) -> Json<DebugInfo> {
+    let started_at = context
+        .started
+        .duration_since(UNIX_EPOCH)
+        .map(|started_at| started_at.as_secs())
+        .unwrap_or_default();
+
+    let uptime_seconds = context
+        .started
+        .elapsed()
+        .map(|uptime| uptime.as_secs())
+        .unwrap_or_default();
+
+    Json(DebugInfo {
+        build: build::info(),
+        environment: context.environment.clone(),
+        pid: process::id(),
+        started_at,
+        uptime_seconds,
+        config: context.effective_config.clone(),
+        config_sources: context.config_sources.clone(),
+    })
+}
+
+// fn get_log_level() returns the global log level, the level of each module, and when a change is reverted.
+pub async fn get_log_level(State(context): State<Arc<DebugContext>>) -> Json<Snapshot> {
+    Json(context.logger.levels().snapshot())
+}
+
+// fn put_log_level() changes the global, or a module log level while running.
+pub async fn put_log_level(
+    State(context): State<Arc<DebugContext>>,
+    Json(payload): Json<DebugPutLogLevel>,
+) -> Result<Json<Snapshot>, SystemError> {
+    let change = Change {
+        module: payload.module,
+        level: payload.level,
+        revert_after: payload.revert_after.map(Duration::from_secs),
+    };
+
+    let snapshot = match context.logger.levels().change(change) {
+        Ok(snapshot) => snapshot,
+        Err(err) => return Err(SystemError::new(StatusCode::BAD_REQUEST, err)),
+    };
+
+    context.logger.warn_w(
+        format!(
+            "log levels changed : global {} : modules {:?} : revert at {:?}",
+            snapshot.global, snapshot.modules, snapshot.revert_at
+        )
+        .as_str(),
+        Some("Debug Log Level"),
+    );
+
+    Ok(Json(snapshot))
+}
+
+// fn to_response() returns the status as JSON, with a 503 when the check failed.
+fn to_response(status: Status) -> impl IntoResponse {
+    let status_code = match status.healthy {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
     };
 
-    Ok(Json("web server status OK"))
+    (status_code, Json(status))
+}
+
+// fn get_reload() returns the outcome of the last config reload, and the reloadable settings in use.
+pub async fn get_reload(State(context): State<Arc<DebugContext>>) -> Json<DebugReload> {
+    Json(DebugReload {
+        status: context.reloader.status(),
+        settings: context.reloader.current(),
+    })
+}
+
+// fn post_reload() reloads the config now, the same as sending SIGHUP. When the config is not valid, the current
+// settings are kept, and the reason is returned.
+pub async fn post_reload(
+    State(context): State<Arc<DebugContext>>,
+) -> Result<Json<DebugReload>, SystemError> {
+    let reloader = context.reloader.clone();
+
+    // Reloading reads the config files, which must not block the runtime.
+    let status =
+        match tokio::task::spawn_blocking(move || reloader.reload("the debug server")).await {
+            Ok(status) => status,
+            Err(err) => {
+                return Err(SystemError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    err.to_string(),
+                ))
+            }
+        };
+
+    if let Some(err) = status.last_error {
+        return Err(SystemError::new(StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+
+    Ok(Json(DebugReload {
+        status,
+        settings: context.reloader.current(),
+    }))
 }
@@ -0,0 +1,325 @@
+use axum::{
+    extract::{ConnectInfo, Extension, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rust_starter_pack::{
+    domain::system::{
+        auth::{
+            auth::{bearer_challenge, Auth, StandardClaims},
+            session::{self, SESSION_COOKIE},
+        },
+        error::error::SystemError,
+    },
+    lib::server::server::ConnectionInfo,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use validator::Validate;
+
+// LoginContext contains any state required when it comes to logging a user in, and out, and managing their mfa.
+#[derive(Clone)]
+pub struct LoginContext {
+    pub auth: Auth,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct V1PostLogin {
+    #[validate(email)]
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct V1PostMfaVerify {
+    pub mfa_token: String,
+    // Either a totp code, or one of the user's recovery codes.
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct V1PostMfaConfirm {
+    pub code: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct V1PostAccountRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct V1PostVerifyEmailConfirm {
+    pub token: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct V1PostPasswordResetConfirm {
+    pub token: String,
+    #[validate(length(min = 8))]
+    pub password: String,
+}
+
+// fn v1_login() is the main handler for (POST /v1/auth/login)
+// Users with mfa enabled are given a short lived mfa token, that must be exchanged at (POST /v1/auth/mfa/verify).
+pub async fn v1_login(
+    State(context): State<Arc<LoginContext>>,
+    ConnectInfo(connection): ConnectInfo<ConnectionInfo>,
+    Json(login): Json<V1PostLogin>,
+) -> Result<Response, SystemError> {
+    if let Err(err) = login.validate() {
+        return Err(SystemError::new(StatusCode::BAD_REQUEST, err.to_string()));
+    }
+
+    // The address is not known for requests over a unix socket.
+    let address = connection.remote_addr.map(|remote_addr| remote_addr.ip());
+
+    let user = match context
+        .auth
+        .login(&login.email, &login.password, address)
+        .await
+    {
+        Ok(user) => user,
+        Err(err) => return Err(err),
+    };
+
+    if user.mfa_enabled {
+        let mfa_token = match context.auth.new_mfa_token(user.id) {
+            Ok(mfa_token) => mfa_token,
+            Err(err) => return Err(err),
+        };
+
+        return Ok(Json(serde_json::json!({
+            "mfa_required": true,
+            "mfa_token": mfa_token,
+        }))
+        .into_response());
+    }
+
+    issue_credentials(&context.auth, user.id).await
+}
+
+// fn v1_verify_mfa() is the main handler for (POST /v1/auth/mfa/verify)
+pub async fn v1_verify_mfa(
+    State(context): State<Arc<LoginContext>>,
+    Json(verify): Json<V1PostMfaVerify>,
+) -> Result<Response, SystemError> {
+    let user_id = match context
+        .auth
+        .verify_mfa(&verify.mfa_token, &verify.code)
+        .await
+    {
+        Ok(user_id) => user_id,
+        Err(err) => return Err(err),
+    };
+
+    issue_credentials(&context.auth, user_id).await
+}
+
+// fn v1_enroll_mfa() is the main handler for (POST /v1/auth/mfa/enroll)
+pub async fn v1_enroll_mfa(
+    Extension(claims): Extension<StandardClaims>,
+    State(context): State<Arc<LoginContext>>,
+) -> Result<impl IntoResponse, SystemError> {
+    let user_id = match claims_user_id(&claims) {
+        Ok(user_id) => user_id,
+        Err(err) => return Err(err),
+    };
+
+    let enrollment = match context.auth.begin_mfa_enrollment(user_id).await {
+        Ok(enrollment) => enrollment,
+        Err(err) => return Err(err),
+    };
+
+    Ok(Json(serde_json::json!({
+        "secret": enrollment.secret,
+        "otpauth_uri": enrollment.uri,
+    })))
+}
+
+// fn v1_confirm_mfa() is the main handler for (POST /v1/auth/mfa/confirm)
+// The recovery codes are only ever returned here.
+pub async fn v1_confirm_mfa(
+    Extension(claims): Extension<StandardClaims>,
+    State(context): State<Arc<LoginContext>>,
+    Json(confirm): Json<V1PostMfaConfirm>,
+) -> Result<impl IntoResponse, SystemError> {
+    let user_id = match claims_user_id(&claims) {
+        Ok(user_id) => user_id,
+        Err(err) => return Err(err),
+    };
+
+    let recovery_codes = match context
+        .auth
+        .confirm_mfa_enrollment(user_id, &confirm.code)
+        .await
+    {
+        Ok(recovery_codes) => recovery_codes,
+        Err(err) => return Err(err),
+    };
+
+    Ok(Json(serde_json::json!({
+        "recovery_codes": recovery_codes,
+    })))
+}
+
+// fn v1_logout() is the main handler for (POST /v1/auth/logout)
+pub async fn v1_logout(
+    State(context): State<Arc<LoginContext>>,
+    headers: HeaderMap,
+) -> Result<Response, SystemError> {
+    if let Some(id) = session::read_cookie(&headers, SESSION_COOKIE) {
+        if let Err(err) = context.auth.end_session(&id).await {
+            return Err(err);
+        }
+    }
+
+    let mut response = StatusCode::NO_CONTENT.into_response();
+
+    if let Err(err) = set_cookies(&mut response, session::clear_cookies(&context.auth.session)) {
+        return Err(err);
+    }
+
+    Ok(response)
+}
+
+// fn v1_request_email_verification() is the main handler for (POST /v1/auth/verify-email/request)
+// We always return accepted, so the response does not reveal whether the account exists.
+pub async fn v1_request_email_verification(
+    State(context): State<Arc<LoginContext>>,
+    Json(request): Json<V1PostAccountRequest>,
+) -> Result<impl IntoResponse, SystemError> {
+    if let Err(err) = request.validate() {
+        return Err(SystemError::new(StatusCode::BAD_REQUEST, err.to_string()));
+    }
+
+    if let Err(err) = context
+        .auth
+        .request_email_verification(&request.email)
+        .await
+    {
+        return Err(err);
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+// fn v1_confirm_email_verification() is the main handler for (POST /v1/auth/verify-email/confirm)
+pub async fn v1_confirm_email_verification(
+    State(context): State<Arc<LoginContext>>,
+    Json(confirm): Json<V1PostVerifyEmailConfirm>,
+) -> Result<impl IntoResponse, SystemError> {
+    if let Err(err) = context
+        .auth
+        .confirm_email_verification(&confirm.token)
+        .await
+    {
+        return Err(err);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// fn v1_request_password_reset() is the main handler for (POST /v1/auth/password-reset/request)
+pub async fn v1_request_password_reset(
+    State(context): State<Arc<LoginContext>>,
+    Json(request): Json<V1PostAccountRequest>,
+) -> Result<impl IntoResponse, SystemError> {
+    if let Err(err) = request.validate() {
+        return Err(SystemError::new(StatusCode::BAD_REQUEST, err.to_string()));
+    }
+
+    if let Err(err) = context.auth.request_password_reset(&request.email).await {
+        return Err(err);
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+// fn v1_confirm_password_reset() is the main handler for (POST /v1/auth/password-reset/confirm)
+pub async fn v1_confirm_password_reset(
+    State(context): State<Arc<LoginContext>>,
+    Json(confirm): Json<V1PostPasswordResetConfirm>,
+) -> Result<impl IntoResponse, SystemError> {
+    if let Err(err) = confirm.validate() {
+        return Err(SystemError::new(StatusCode::BAD_REQUEST, err.to_string()));
+    }
+
+    if let Err(err) = context
+        .auth
+        .confirm_password_reset(&confirm.token, &confirm.password)
+        .await
+    {
+        return Err(err);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// fn issue_credentials() completes a login. When sessions are enabled, the session is returned as cookies,
+// otherwise we return a bearer token.
+async fn issue_credentials(auth: &Auth, user_id: i32) -> Result<Response, SystemError> {
+    if !auth.session.enabled {
+        let token = match auth.new_token(user_id).await {
+            Ok(token) => token,
+            Err(err) => return Err(err),
+        };
+
+        return Ok(Json(serde_json::json!({
+            "access_token": token,
+            "token_type": "Bearer",
+        }))
+        .into_response());
+    }
+
+    let new_session = match auth.new_session(user_id).await {
+        Ok(new_session) => new_session,
+        Err(err) => return Err(err),
+    };
+
+    // The csrf token is also returned in the body, so the frontend does not need to parse cookies.
+    let mut response = Json(serde_json::json!({
+        "csrf_token": new_session.csrf_token,
+    }))
+    .into_response();
+
+    if let Err(err) = set_cookies(
+        &mut response,
+        session::session_cookies(&auth.session, &new_session),
+    ) {
+        return Err(err);
+    }
+
+    Ok(response)
+}
+
+// fn claims_user_id() returns the id of the authenticated user from the claims of the request.
+fn claims_user_id(claims: &StandardClaims) -> Result<i32, SystemError> {
+    match claims.sub.parse::<i32>() {
+        Ok(user_id) => Ok(user_id),
+        Err(_) => Err(SystemError::new_unauthorised(
+            bearer_challenge(None, None),
+            "an authenticated user is required",
+        )),
+    }
+}
+
+// fn set_cookies() appends each cookie as its own Set-Cookie header.
+fn set_cookies(response: &mut Response, cookies: Vec<String>) -> Result<(), SystemError> {
+    for cookie in cookies {
+        let value = match HeaderValue::from_str(&cookie) {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(SystemError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("could not create cookie : {}", err),
+                ))
+            }
+        };
+
+        response.headers_mut().append(header::SET_COOKIE, value);
+    }
+
+    Ok(())
+}
@@ -5,7 +5,7 @@ use axum::{
 };
 use rust_starter_pack::{
     core::user::user::{UserCore, V1PostUser},
-    domain::{system::error::error::SystemError, web::state::state::SharedState},
+    domain::system::{auth::auth::StandardClaims, error::error::SystemError},
 };
 use std::sync::Arc;
 use validator::Validate;
@@ -13,7 +13,7 @@ use validator::Validate;
 // * Any errors returned from handler functions, will be caught and then processed in middleware.
 
 // * Entrypoint Handlers deal with the following things.
-// * 1. Lock/Read the web state
+// * 1. Extract the claims of the authenticated user
 // * 2. Validate the request params/body
 // * 3. Loading the context to run the core function.
 // * 4. Return the response or the error up the stack.
@@ -27,13 +27,11 @@ pub struct UserContext {
 
 // fn v1_get_users() is the main handler for (GET /v1/users)
 pub async fn v1_get_users(
-    Extension(state): Extension<SharedState>,
+    Extension(claims): Extension<StandardClaims>,
     State(context): State<Arc<UserContext>>,
 ) -> Result<impl IntoResponse, SystemError> {
-    let state = &state.read().await;
-
     // Once validated, or doing any logic involving the request, we send to our core entrypoint function.
-    let result = match context.user_core.get_all(state).await {
+    let result = match context.user_core.get_all(&claims).await {
         Ok(result) => result,
         Err(err) => {
             return Err(err);
@@ -45,13 +43,12 @@ pub async fn v1_get_users(
 
 // fn v1_get_user_by_id() is the main handler for (GET /v1/users/{id})
 pub async fn v1_get_user_by_id(
-    Extension(state): Extension<SharedState>,
+    Extension(claims): Extension<StandardClaims>,
     State(context): State<Arc<UserContext>>,
     Path(id): Path<i32>,
 ) -> Result<impl IntoResponse, SystemError> {
-    let state = &state.read().await;
     // Once validated, or doing any logic involving the request, we send to our core entrypoint function.
-    let result = match context.user_core.get_by_id(&state, id).await {
+    let result = match context.user_core.get_by_id(&claims, id).await {
         Ok(result) => result,
         Err(err) => {
             return Err(err);
@@ -63,11 +60,10 @@ pub async fn v1_get_user_by_id(
 
 // fn v1_get_user_by_id() is the main handler for (POST /v1/users)
 pub async fn v1_post_user(
-    Extension(state): Extension<SharedState>,
+    Extension(claims): Extension<StandardClaims>,
     State(context): State<Arc<UserContext>>,
     Json(user): Json<V1PostUser>,
 ) -> Result<impl IntoResponse, SystemError> {
-    let state = &state.read().await;
     // Before sending off to core logic, for request bodies, we should validate it.
     if let Err(err) = user.validate() {
         return Err(SystemError::new(
@@ -77,7 +73,7 @@ pub async fn v1_post_user(
     }
 
     // Once validated, or doing any logic involving the request, we send to our core entrypoint function.
-    if let Err(err) = context.user_core.create(&state, user).await {
+    if let Err(err) = context.user_core.create(&claims, user).await {
         return Err(err);
     }
 
@@ -1,19 +1,33 @@
 use super::handlers::debug::debug::{self, DebugContext};
+use super::handlers::v1::auth::{self as login, LoginContext};
 use super::handlers::v1::users::{self, UserContext};
+use crate::config::Reloadable;
 use axum::routing::{get, post};
 use axum::{middleware, Extension, Router};
 use rust_starter_pack::core::user::user;
-use rust_starter_pack::domain::system::auth::auth::{self, StandardClaims};
+use rust_starter_pack::domain::system::auth::auth;
 use rust_starter_pack::domain::web::middleware::audit::{audit, AuditContext};
-use rust_starter_pack::domain::web::middleware::auth::{authenticate, AuthContext};
+use rust_starter_pack::domain::web::middleware::auth::{
+    authenticate, authorise, AuthContext, RoleContext,
+};
+use rust_starter_pack::domain::web::middleware::cors::{cors, CorsConfig, CorsContext};
 use rust_starter_pack::domain::web::middleware::error::{error, ErrorContext};
 use rust_starter_pack::domain::web::middleware::logging::{logging, LoggingContext};
+use rust_starter_pack::domain::web::middleware::metrics::metrics;
 use rust_starter_pack::domain::web::state::state::{MuxState, SharedState};
+use rust_starter_pack::lib::conf::{conf::Source, reload::Reloader};
+use rust_starter_pack::lib::database::database;
+use rust_starter_pack::lib::features::features::Features;
+use rust_starter_pack::lib::health::health;
 use rust_starter_pack::lib::logger::logger;
-use rust_starter_pack::lib::server::server::{self, Axum};
-use sqlx::{postgres, PgPool};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use rust_starter_pack::lib::server::{
+    limits, listener,
+    registry::{Probe, Registry},
+    server::{self, Axum},
+    tls,
+};
+use std::{collections::BTreeMap, sync::Arc, time::SystemTime};
+use tokio::sync::{watch, RwLock};
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 // Mux acts as the multiplexer in order to configure and create our services that acts as the main layer
@@ -23,38 +37,73 @@ use tower_http::trace::TraceLayer;
 // features they require to perform business operations.
 pub struct MuxConfig<'a> {
     pub environment: String,
+    // When the service started, and its config with sensitive settings redacted, both shown at /debug/info.
+    pub started: SystemTime,
+    pub effective_config: serde_json::Value,
+    // Where each setting in the config came from.
+    pub config_sources: BTreeMap<String, Source>,
     pub web_address: String,
     pub web_port: u16,
     pub debug_address: String,
     pub debug_port: u16,
+    // The permissions of any unix sockets we create.
+    pub socket_mode: Option<u32>,
+    // When provided, the web server is served over HTTPS. The debug server is always plain HTTP.
+    pub tls: Option<tls::Config>,
+    // Applied to both the web and debug server.
+    pub limits: limits::Config,
+    // How the debug server retries its health checks.
+    pub health: health::Config,
+    // The checks served as /livez, /readyz and /startupz on the debug server.
+    pub registry: Registry,
     // Custom Packages
     pub logger: &'a logger::Logger,
-    pub db: postgres::PgPool,
+    pub db: database::Database,
     pub auth: auth::Auth,
+    pub public_routes: Vec<String>,
+    // Settings that can change while running, these are reloaded on SIGHUP, or when a config file changes.
+    pub cors: watch::Receiver<CorsConfig>,
+    pub features: Features,
+    pub reloader: Reloader<Reloadable>,
 }
 
 // fn new_mux() creates two isolated web services, a debug service, and web service.
 // Web service acts as the main service that handles incoming requests, and processes them.
-// Debug service acts as the debug server that will contain metrics, and alerting.
+// Debug service acts as the debug server that contains metrics, and health checks.
 pub fn new_mux(config: MuxConfig) -> Result<(Axum, Axum), axum::Error> {
     // Firstly, we create our tracing support.
 
     // TODO - tracing needs to be more granula and contain actual logging.
     let tracer: Router = Router::new().layer(TraceLayer::new_for_http());
 
+    // Register the checks of our components, before the debug routes serve them.
+    register_health_checks(&config);
+
     // Initialise our global web state that is shared across the project.
     // Global state uses A mutex to safely read and write to the state without any side effects.
     let global_state = SharedState::new(RwLock::new(MuxState {
         environment: config.environment.clone(),
-        claims: StandardClaims::default(),
     }));
 
+    // Logging in (both steps), and the email based account flows must always be reachable without a token,
+    // alongside any configured public routes.
+    let mut public_routes = config.public_routes.clone();
+    public_routes.push(String::from("/v1/auth/login"));
+    public_routes.push(String::from("/v1/auth/mfa/verify"));
+    public_routes.push(String::from("/v1/auth/verify-email/request"));
+    public_routes.push(String::from("/v1/auth/verify-email/confirm"));
+    public_routes.push(String::from("/v1/auth/password-reset/request"));
+    public_routes.push(String::from("/v1/auth/password-reset/confirm"));
+
     // * Initialise our v1 routes with our application level middleware, and shared state.
     // Create V1 route handlers.
     let v1_routes = initialise_v1_web_routing(&config)
         .layer(
             // We use ServiceBuilder as this means that the order of middleware is from top to bottom.
             ServiceBuilder::new()
+                // * Metrics
+                // First, so the latency and status include every other middleware.
+                .layer(middleware::from_fn(metrics))
                 // * Logging
                 .layer(middleware::from_fn_with_state(
                     LoggingContext {
@@ -62,6 +111,14 @@ pub fn new_mux(config: MuxConfig) -> Result<(Axum, Axum), axum::Error> {
                     },
                     logging,
                 ))
+                // * CORS
+                // Before authentication, as browsers never send a token with a preflight request.
+                .layer(middleware::from_fn_with_state(
+                    CorsContext {
+                        config: config.cors.clone(),
+                    },
+                    cors,
+                ))
                 // * Error handling
                 .layer(middleware::from_fn_with_state(
                     ErrorContext {
@@ -70,8 +127,13 @@ pub fn new_mux(config: MuxConfig) -> Result<(Axum, Axum), axum::Error> {
                     error,
                 ))
                 // * Authentication
+                // Routes listed in public_routes can be reached without a token. The claims of the request are
+                // available to handlers with Extension<StandardClaims>.
                 .layer(middleware::from_fn_with_state(
-                    AuthContext { auth: config.auth },
+                    AuthContext {
+                        auth: config.auth.clone(),
+                        public_routes,
+                    },
                     authenticate,
                 ))
                 // * Auditing
@@ -82,7 +144,9 @@ pub fn new_mux(config: MuxConfig) -> Result<(Axum, Axum), axum::Error> {
                     audit,
                 )),
         )
-        .layer(Extension(global_state));
+        .layer(Extension(global_state))
+        // Handlers check feature flags with Extension<Features>.
+        .layer(Extension(config.features.clone()));
 
     // Here we lastly create our new muxes, and then return to main in order to block the application
     // As stated before, this will be in a seperate thread so we can have multiple senders potentially
@@ -90,14 +154,17 @@ pub fn new_mux(config: MuxConfig) -> Result<(Axum, Axum), axum::Error> {
     let web_mux = server::new(server::Config {
         web_address: config.web_address.clone(),
         port: config.web_port,
+        socket_mode: config.socket_mode,
         // Here we merge our versioned routes with our application middleware.
         // It is important to note that route layers (like middleware) need to wrap around routes, so the router
         // needs to contain the routes before the middleware.
         router: tracer.clone().merge(v1_routes),
+        tls: config.tls.clone(),
+        limits: config.limits.clone(),
     });
 
     // Create Debug route handlers.
-    let debug_routes = initialise_debug_routing(config.db.clone()).layer(
+    let debug_routes = initialise_debug_routing(&config).layer(
         ServiceBuilder::new()
             // * Logging
             .layer(middleware::from_fn_with_state(
@@ -111,29 +178,81 @@ pub fn new_mux(config: MuxConfig) -> Result<(Axum, Axum), axum::Error> {
     let debug_mux = server::new(server::Config {
         web_address: config.debug_address.clone(),
         port: config.debug_port,
+        socket_mode: config.socket_mode,
         router: tracer.clone().merge(debug_routes),
+        tls: None,
+        limits: config.limits.clone(),
     });
 
     Ok((web_mux, debug_mux))
 }
 
-// fn initialise_debug_routing creates our debug routes, for now, this just contains a root path that pings itself.
-// This initial route will help in understanding if the debug service is experiencing any down time.
-// But this service can also provide liveness, and readiness checks for our main web server.
-fn initialise_debug_routing(db: PgPool) -> axum::Router {
+// fn initialise_debug_routing creates our debug routes, this contains a route that pings the web server, a route that
+// checks the database, and the /livez, /readyz and /startupz probes from the health registry.
+fn initialise_debug_routing(config: &MuxConfig) -> axum::Router {
     let debug_context = DebugContext {
         version: String::from("v1"),
-        db: db,
-        web_address: String::from("http://host.docker.internal"),
-        web_port: 8128,
+        db: config.db.clone(),
+        web_address: config.web_address.clone(),
+        web_port: config.web_port,
+        web_tls: config.tls.is_some(),
+        health: config.health.clone(),
+        logger: config.logger.clone(),
+        environment: config.environment.clone(),
+        started: config.started,
+        effective_config: config.effective_config.clone(),
+        config_sources: config.config_sources.clone(),
+        reloader: config.reloader.clone(),
     };
 
     let debug_router = axum::Router::new() // We provide a base route to ping.
         .route("/debug/web", get(debug::check_web_server_status))
         .route("/debug/database", get(debug::check_database_status))
+        .route("/metrics", get(debug::get_metrics))
+        .route("/debug/info", get(debug::get_info))
+        .route(
+            "/debug/log-level",
+            get(debug::get_log_level).put(debug::put_log_level),
+        )
+        .route(
+            "/debug/config/reload",
+            get(debug::get_reload).post(debug::post_reload),
+        )
         .with_state(Arc::new(debug_context));
 
-    debug_router
+    debug_router.merge(config.registry.router())
+}
+
+// fn register_health_checks() registers the web server, and database with the health registry.
+// The web server is pinged for every probe, the database only decides if we are ready to receive traffic.
+fn register_health_checks(config: &MuxConfig) {
+    // A socket passed in by systemd has no address we can reach it on, so it is not pinged.
+    if !config.web_address.starts_with(listener::FD_PREFIX) {
+        let web_address = config.web_address.clone();
+        let web_port = config.web_port;
+        let web_tls = config.tls.is_some();
+        let health = config.health.clone();
+
+        config.registry.register(
+            &[Probe::Liveness, Probe::Readiness, Probe::Startup],
+            move || {
+                let web_address = web_address.clone();
+                let health = health.clone();
+                async move { server::liveness_check(&web_address, web_port, web_tls, &health).await }
+            },
+        );
+    }
+
+    let db = config.db.clone();
+    let health = config.health.clone();
+
+    config
+        .registry
+        .register(&[Probe::Readiness, Probe::Startup], move || {
+            let db = db.clone();
+            let health = health.clone();
+            async move { database::readiness_check(&db, &health).await }
+        });
 }
 
 // fn initialise_v1_web_routing creates our main web service that contains routes that handle our core business logic.
@@ -154,12 +273,62 @@ fn initialise_v1_web_routing(config: &MuxConfig) -> axum::Router {
         // * GET ( /v1/users/:id )
         .route("/v1/users/:id", get(users::v1_get_user_by_id))
         // * POST ( /v1/users )
-        .route("/v1/users", post(users::v1_post_user))
+        // Only admins can create users.
+        .route(
+            "/v1/users",
+            post(users::v1_post_user).route_layer(middleware::from_fn_with_state(
+                RoleContext {
+                    auth: config.auth.clone(),
+                    roles: vec![String::from("admin")],
+                },
+                authorise,
+            )),
+        )
         // * Create context for users using Arc.
         .with_state(Arc::new(user_context));
 
+    // Create login handler that will act as the context for auth routes.
+    let login_context = LoginContext {
+        auth: config.auth.clone(),
+    };
+
+    // Build our router for auth.
+    let login_router = axum::Router::new()
+        // * POST ( /v1/auth/login )
+        .route("/v1/auth/login", post(login::v1_login))
+        // * POST ( /v1/auth/logout )
+        .route("/v1/auth/logout", post(login::v1_logout))
+        // * POST ( /v1/auth/mfa/verify )
+        .route("/v1/auth/mfa/verify", post(login::v1_verify_mfa))
+        // * POST ( /v1/auth/mfa/enroll )
+        .route("/v1/auth/mfa/enroll", post(login::v1_enroll_mfa))
+        // * POST ( /v1/auth/mfa/confirm )
+        .route("/v1/auth/mfa/confirm", post(login::v1_confirm_mfa))
+        // * POST ( /v1/auth/verify-email/request )
+        .route(
+            "/v1/auth/verify-email/request",
+            post(login::v1_request_email_verification),
+        )
+        // * POST ( /v1/auth/verify-email/confirm )
+        .route(
+            "/v1/auth/verify-email/confirm",
+            post(login::v1_confirm_email_verification),
+        )
+        // * POST ( /v1/auth/password-reset/request )
+        .route(
+            "/v1/auth/password-reset/request",
+            post(login::v1_request_password_reset),
+        )
+        // * POST ( /v1/auth/password-reset/confirm )
+        .route(
+            "/v1/auth/password-reset/confirm",
+            post(login::v1_confirm_password_reset),
+        )
+        // * Create context for auth using Arc.
+        .with_state(Arc::new(login_context));
+
     // * More routes go below
 
     // We return all merged routes here with their own state.
-    axum::Router::new().merge(user_router)
+    axum::Router::new().merge(user_router).merge(login_router)
 }
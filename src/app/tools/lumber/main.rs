@@ -2,6 +2,7 @@
 mod commands;
 
 use log::LevelFilter;
+use rust_starter_pack::lib::build::build;
 use rust_starter_pack::lib::logger::logger::{self, Config, Logger};
 use std::{env, error::Error, process::exit};
 
@@ -9,6 +10,7 @@ fn main() {
     env::set_var("RUST_LOG", "info");
     let logger = logger::new_logger(Config {
         name: String::from("LUMBER"),
+        version: build::version(),
         max_log_level: LevelFilter::Info,
     });
 
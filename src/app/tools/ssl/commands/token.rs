@@ -1,8 +1,9 @@
 use rust_starter_pack::{
-    domain::system::auth::auth,
+    domain::system::auth::{account::AccountConfig, auth, mfa::MfaConfig, session::SessionConfig},
     lib::{database::database, logger::logger::Logger},
 };
-use std::error::Error;
+use std::{error::Error, time::Duration};
+use tokio::sync::watch;
 
 // Lots of cleanup to do here.
 
@@ -10,13 +11,21 @@ pub async fn make_token(log: &Logger) -> Result<(), Box<dyn Error>> {
     // -----------------------------------------------------------
     // Custom postgres configuration, and initialsation.
     let database_config = database::Config {
+        url: None,
         db_host: String::from("localhost"),
         db_port: 5439,
         db_username: String::from("postgres"),
         db_password: String::from("example"),
         db_schema: String::from("postgres"),
+        ssl_mode: sqlx::postgres::PgSslMode::Disable,
+        ssl_root_cert: None,
+        application_name: String::from("ssl"),
+        statement_timeout: None,
+        min_connections: 0,
         max_connections: 2,
-        enable_ssl: sqlx::postgres::PgSslMode::Disable,
+        acquire_timeout: Duration::from_secs(5),
+        idle_timeout: None,
+        max_lifetime: None,
     };
 
     let db = match database::open_postgres_database(database_config).await {
@@ -29,9 +38,24 @@ pub async fn make_token(log: &Logger) -> Result<(), Box<dyn Error>> {
     // Get this from env.
     let auth = auth::new(auth::AuthConfig {
         enabled: true,
-        key_id: String::from("72e8cca8-28a8-40e5-81bd-c1dbc7cfc5ee"),
+        keys: watch::channel(auth::KeyRing {
+            signing: String::from("72e8cca8-28a8-40e5-81bd-c1dbc7cfc5ee"),
+            previous: vec![],
+        })
+        .1,
         signing_method: jsonwebtoken::Algorithm::RS256,
-        db: db,
+        db: database::new(
+            db,
+            None,
+            database::Instrumentation {
+                logger: log.clone(),
+                slow_query: None,
+            },
+        ),
+        session: SessionConfig::default(),
+        account: AccountConfig::default(),
+        mfa: MfaConfig::default(),
+        login: auth::LoginConfig::default(),
     });
 
     let token = match auth.new_token(1).await {
@@ -1,4 +1,5 @@
 use log::LevelFilter;
+use rust_starter_pack::lib::build::build;
 use rust_starter_pack::lib::logger::logger;
 use rust_starter_pack::lib::logger::logger::Logger;
 use std::env;
@@ -11,6 +12,7 @@ async fn main() {
     env::set_var("RUST_LOG", "info");
     let logger = logger::new_logger(logger::Config {
         name: String::from("OPENSSL-GEN"),
+        version: build::version(),
         max_log_level: LevelFilter::Info,
     });
 
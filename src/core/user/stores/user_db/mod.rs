@@ -2,7 +2,8 @@ pub mod user_db;
 
 // * mod.rs makes sense to also contain the models for the module.
 use serde::Serialize;
-// Store Struct that represents the User, as is stored in the database.
+// Store Struct that represents the User, as is stored in the database. Rows are mapped to it by column name, so a
+// query selecting these columns can return it from fn query_single_row() or fn query_many_rows().
 #[derive(sqlx::FromRow, Serialize)]
 pub struct User {
     pub email: String,
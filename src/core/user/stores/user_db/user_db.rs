@@ -1,18 +1,17 @@
 use super::User;
 use crate::core::user::user::V1PostUser;
-use crate::lib::database;
+use crate::lib::database::{self, database::Database};
 use crate::lib::logger::logger::Logger;
 use sqlx::error::Error;
-use sqlx::{PgPool, Row};
 
 #[derive(Clone)]
 pub struct UserStore {
     pub logger: Logger,
-    pub db: PgPool,
+    pub db: Database,
 }
 
 // fn new_store() creates a new user store to perform database operations for the entity users.
-pub fn new_store(logger: Logger, db: PgPool) -> UserStore {
+pub fn new_store(logger: Logger, db: Database) -> UserStore {
     UserStore {
         logger: logger,
         db: db,
@@ -35,27 +34,18 @@ impl UserStore {
         // Provide the statement.
         let statement = sqlx::query(query);
 
-        // Log query to the console.
-        self.logger
-            .info_w("selecting all users... : query : ", Some(query));
-
-        // Fetch all rows of users by using fn query_many_rows()
-        let rows = match database::database::query_many_rows(&self.db, statement).await {
-            Ok(rows) => rows,
+        // Fetch all rows of users by using fn query_many_rows(), each row is mapped to a User by its FromRow derive.
+        let users = match database::database::query_many_rows::<User>(
+            &self.db,
+            "users.select_all",
+            statement,
+        )
+        .await
+        {
+            Ok(users) => users,
             Err(err) => return Err(err),
         };
 
-        // Map the rows back into our concrete type Vector of User structs.
-        let users = rows
-            .iter()
-            .map(|row| User {
-                email: row.get("email"),
-                first_name: row.get("first_name"),
-                last_name: row.get("last_name"),
-                role: row.get("role"),
-            })
-            .collect();
-
         Ok(users)
     }
 
@@ -69,44 +59,43 @@ impl UserStore {
         // Provide the statement.
         let statement = sqlx::query(query).bind(id);
 
-        // Log query to the console.
-        self.logger
-            .info_w("selecting user by id... : query : ", Some(query));
-
-        // Fetch a single row of a user by using fn query_single_row()
-        let row = match database::database::query_single_row(&self.db, statement).await {
-            Ok(rows) => rows,
+        // Fetch a single row of a user by using fn query_single_row(), mapped to a User by its FromRow derive.
+        let user = match database::database::query_single_row::<User>(
+            &self.db,
+            "users.select_by_id",
+            statement,
+        )
+        .await
+        {
+            Ok(user) => user,
             Err(err) => return Err(err),
         };
 
-        // Map a single user struct to the returned rows given by the query.
-        Ok(User {
-            email: row.get("email"),
-            first_name: row.get("first_name"),
-            last_name: row.get("last_name"),
-            role: row.get("role"),
-        })
+        Ok(user)
     }
 
-    pub async fn create_user(&self, user: V1PostUser) -> Result<(), sqlx::Error> {
+    pub async fn create_user(
+        &self,
+        user: V1PostUser,
+        password_hash: Option<String>,
+    ) -> Result<(), sqlx::Error> {
         // Create our raw query string.
         let query = "
-        INSERT INTO users(email, first_name, last_name, role)
-        VALUES ($1,$2,$3,$4)";
+        INSERT INTO users(email, first_name, last_name, role, password_hash)
+        VALUES ($1,$2,$3,$4,$5)";
 
         // Provide the statement.
         let statement = sqlx::query(query)
             .bind(user.email)
             .bind(user.first_name)
             .bind(user.last_name)
-            .bind(user.role);
-
-        // Log query to the console.
-        self.logger
-            .info_w("creating user... : query : ", Some(query));
+            .bind(user.role)
+            .bind(password_hash);
 
         // Insert a new user record into the database using the mutate_statement()
-        if let Err(err) = database::database::mutate_statement(&self.db, statement).await {
+        if let Err(err) =
+            database::database::mutate_statement(&self.db, "users.insert", statement).await
+        {
             return Err(err);
         }
 
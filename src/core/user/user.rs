@@ -2,10 +2,13 @@ use super::stores::user_db::{
     user_db::{self, UserStore},
     User,
 };
-use crate::domain::{system::error::error::SystemError, web::state::state::MuxState};
+use crate::domain::system::{
+    auth::{auth::StandardClaims, password},
+    error::error::SystemError,
+};
+use crate::lib::database::database::Database;
 use crate::lib::logger::logger::Logger;
 use serde::Deserialize;
-use sqlx::PgPool;
 use validator::Validate;
 
 #[derive(Deserialize, Validate)]
@@ -15,6 +18,9 @@ pub struct V1PostUser {
     pub first_name: String,
     pub last_name: String,
     pub role: String,
+    // Users without a password can only authenticate with a token issued by the ssl tool.
+    #[validate(length(min = 8))]
+    pub password: Option<String>,
 }
 
 #[derive(Clone)]
@@ -23,7 +29,7 @@ pub struct UserCore {
 }
 
 // fn new_core() constructs a new core to perform core business logic for users.
-pub fn new_core(logger: &Logger, db: &PgPool) -> UserCore {
+pub fn new_core(logger: &Logger, db: &Database) -> UserCore {
     UserCore {
         user_store: user_db::new_store(logger.clone(), db.clone()),
     }
@@ -35,10 +41,10 @@ pub fn new_core(logger: &Logger, db: &PgPool) -> UserCore {
 // One example can be business/core/user/clients/[grpc, rest] that will allow this core to send requests.
 impl UserCore {
     // fn v1_get_users() is the core entrypoint to start user business logic for getting all users.
-    pub async fn get_all(&self, state: &MuxState) -> Result<Vec<User>, SystemError> {
+    pub async fn get_all(&self, claims: &StandardClaims) -> Result<Vec<User>, SystemError> {
         println!(
             "You are authed as {} with the role {}",
-            state.claims.email, state.claims.role
+            claims.email, claims.role
         );
 
         let result = match self.user_store.query_users().await {
@@ -54,10 +60,10 @@ impl UserCore {
         Ok(result)
     }
     // fn v1_get_users_by_id() is the core entrypoint to start user business logic for getting a user by id.
-    pub async fn get_by_id(&self, state: &MuxState, id: i32) -> Result<User, SystemError> {
+    pub async fn get_by_id(&self, claims: &StandardClaims, id: i32) -> Result<User, SystemError> {
         println!(
             "You are authed as {} with the role {}",
-            state.claims.email, state.claims.role
+            claims.email, claims.role
         );
         let result = match self.user_store.query_user_by_id(id).await {
             Ok(result) => result,
@@ -71,12 +77,21 @@ impl UserCore {
         Ok(result)
     }
     // fn v1_post_user() is the core entrypoint to start user business logic for creating a new user.
-    pub async fn create(&self, state: &MuxState, user: V1PostUser) -> Result<(), SystemError> {
+    pub async fn create(&self, claims: &StandardClaims, user: V1PostUser) -> Result<(), SystemError> {
         println!(
             "You are authed as {} with the role {}",
-            state.claims.email, state.claims.role
+            claims.email, claims.role
         );
-        if let Err(err) = self.user_store.create_user(user).await {
+        // We never store the plain text password, only its hash.
+        let password_hash = match &user.password {
+            Some(password) => match password::spawn_hash_password(password.clone()).await {
+                Ok(hash) => Some(hash),
+                Err(err) => return Err(err),
+            },
+            None => None,
+        };
+
+        if let Err(err) = self.user_store.create_user(user, password_hash).await {
             return Err(SystemError::new(
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR,
                 err.to_string(),
@@ -0,0 +1,255 @@
+use super::session::random_token;
+use crate::{
+    domain::system::error::error::SystemError,
+    lib::{
+        database::database::{self, Database},
+        limiter::limiter::{self, Limiter},
+        mailer::mailer::Mailer,
+    },
+};
+use axum::http::StatusCode;
+use sqlx::{
+    postgres::{PgArguments, Postgres},
+    query::Query,
+};
+use std::time::Duration;
+
+// Account abstracts away the single use tokens for verifying an email address, and resetting a password.
+
+// The purposes a single use token can be stored for.
+pub const VERIFY_EMAIL: &str = "verify_email";
+pub const PASSWORD_RESET: &str = "password_reset";
+
+// The configuration for our account flows.
+#[derive(Clone)]
+pub struct AccountConfig {
+    pub mailer: Mailer,
+    // Limits how many emails can be requested per address.
+    pub limiter: Limiter,
+    // The url of the frontend, the token is appended to the links we send.
+    pub link_url: String,
+    pub verify_email_lifetime: Duration,
+    pub password_reset_lifetime: Duration,
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        AccountConfig {
+            mailer: Mailer::default(),
+            limiter: limiter::new(limiter::Config {
+                max_attempts: 3,
+                window: Duration::from_secs(60 * 60),
+            }),
+            link_url: String::from("http://localhost"),
+            verify_email_lifetime: Duration::from_secs(24 * 60 * 60),
+            password_reset_lifetime: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+// A user found by their email address.
+#[derive(sqlx::FromRow)]
+pub struct AccountUser {
+    pub id: i32,
+    pub email: String,
+    pub email_verified: bool,
+}
+
+// pub fn normalise_email() is applied to an address before it is looked up, emails are matched case insensitively
+// so the users table can keep them as they were entered.
+pub fn normalise_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// pub async fn find_user() finds the user with the given normalised email, if one exists.
+pub async fn find_user(db: &Database, email: &str) -> Result<Option<AccountUser>, SystemError> {
+    let query = "
+        SELECT id, email, email_verified_at IS NOT NULL AS email_verified
+        FROM users
+        WHERE lower(email) = $1";
+
+    let statement = sqlx::query(query).bind(email);
+
+    let users = match database::query_many_rows::<AccountUser>(
+        db,
+        "users.select_by_email",
+        statement,
+    )
+    .await
+    {
+        Ok(users) => users,
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not fetch user by email : {}", err),
+            ))
+        }
+    };
+
+    Ok(users.into_iter().next())
+}
+
+// pub async fn store_token() records a new single use token, and returns its id to be signed into the token.
+pub async fn store_token(
+    db: &Database,
+    user_id: i32,
+    purpose: &str,
+    lifetime: Duration,
+) -> Result<String, SystemError> {
+    let jti = match random_token(16) {
+        Ok(jti) => jti,
+        Err(err) => return Err(err),
+    };
+
+    let query = "
+        INSERT INTO user_tokens (jti, user_id, purpose, expires_at)
+        VALUES ($1, $2, $3, now() + make_interval(secs => $4))";
+
+    let statement = sqlx::query(query)
+        .bind(&jti)
+        .bind(user_id)
+        .bind(purpose)
+        .bind(lifetime.as_secs() as f64);
+
+    if let Err(err) = database::mutate_statement(db, "user_tokens.insert", statement).await {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not store {} token : {}", purpose, err),
+        ));
+    }
+
+    Ok(jti)
+}
+
+// pub async fn consume_token() marks the token as used, a token that has already been used, has expired, or
+// does not belong to the user is rejected.
+pub async fn consume_token(
+    db: &Database,
+    jti: &str,
+    user_id: i32,
+    purpose: &str,
+) -> Result<(), SystemError> {
+    let statement = consume_statement(jti, user_id, purpose);
+
+    consumed(
+        database::mutate_statement(db, "user_tokens.consume", statement).await,
+        purpose,
+    )
+}
+
+// fn consume_statement() builds the update that marks the token as used.
+fn consume_statement<'a>(
+    jti: &'a str,
+    user_id: i32,
+    purpose: &'a str,
+) -> Query<'a, Postgres, PgArguments> {
+    let query = "
+        UPDATE user_tokens SET used_at = now()
+        WHERE jti = $1 AND user_id = $2 AND purpose = $3 AND used_at IS NULL AND expires_at > now()";
+
+    sqlx::query(query).bind(jti).bind(user_id).bind(purpose)
+}
+
+// fn consumed() maps the rows affected by the consume statement, exactly one row must have been updated.
+fn consumed(result: Result<u64, sqlx::Error>, purpose: &str) -> Result<(), SystemError> {
+    match result {
+        Ok(1) => Ok(()),
+        Ok(_) => Err(SystemError::new(
+            StatusCode::BAD_REQUEST,
+            "the token has already been used or has expired",
+        )),
+        Err(err) => Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not use {} token : {}", purpose, err),
+        )),
+    }
+}
+
+// pub async fn mark_email_verified() records that the user has verified their email address.
+pub async fn mark_email_verified(db: &Database, user_id: i32) -> Result<(), SystemError> {
+    let query = "UPDATE users SET email_verified_at = now() WHERE id = $1";
+    let statement = sqlx::query(query).bind(user_id);
+
+    if let Err(err) = database::mutate_statement(db, "users.update_email_verified", statement).await
+    {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not verify email : {}", err),
+        ));
+    }
+
+    Ok(())
+}
+
+// pub async fn reset_password() consumes the reset token, sets the new password hash, and then removes every
+// session and outstanding reset token for the user, so anyone with access to the old password is logged out.
+// Every statement runs in one transaction, so the token is only used up once the password has been changed, and
+// the password is never changed while the old sessions remain.
+pub async fn reset_password(
+    db: &Database,
+    jti: &str,
+    user_id: i32,
+    password_hash: String,
+) -> Result<(), SystemError> {
+    let mut transaction = match database::begin_write(db).await {
+        Ok(transaction) => transaction,
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not update password : {}", err),
+            ))
+        }
+    };
+
+    let statement = consume_statement(jti, user_id, PASSWORD_RESET);
+
+    // Returning before the commit rolls back the transaction.
+    if let Err(err) = consumed(
+        database::mutate_in_transaction(db, &mut transaction, "user_tokens.consume", statement)
+            .await,
+        PASSWORD_RESET,
+    ) {
+        return Err(err);
+    }
+
+    let statements = [
+        (
+            "users.update_password",
+            sqlx::query("UPDATE users SET password_hash = $1, updated_at = now() WHERE id = $2")
+                .bind(password_hash)
+                .bind(user_id),
+        ),
+        (
+            "sessions.delete_for_user",
+            sqlx::query("DELETE FROM sessions WHERE user_id = $1").bind(user_id),
+        ),
+        (
+            "user_tokens.use_password_resets",
+            sqlx::query(
+                "UPDATE user_tokens SET used_at = now() WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL",
+            )
+            .bind(user_id)
+            .bind(PASSWORD_RESET),
+        ),
+    ];
+
+    for (name, statement) in statements {
+        if let Err(err) =
+            database::mutate_in_transaction(db, &mut transaction, name, statement).await
+        {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not update password : {}", err),
+            ));
+        }
+    }
+
+    if let Err(err) = transaction.commit().await {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not update password : {}", err),
+        ));
+    }
+
+    Ok(())
+}
@@ -1,26 +1,94 @@
-use super::{decode, encode::encode_token};
-use crate::domain::{system::error::error::SystemError, web::state::state::MuxState};
-use hyper::StatusCode;
+use super::{
+    account::{self, AccountConfig},
+    decode,
+    encode::{self, encode_purpose_token, encode_token},
+    mfa::{self, Enrollment, MfaConfig},
+    password,
+    session::{self, random_token, NewSession, SessionConfig},
+};
+use crate::{
+    domain::system::error::error::SystemError,
+    lib::{
+        database::database::{self, Database},
+        limiter::limiter::{self, Limiter},
+        mailer::mailer::Mail,
+    },
+};
+use axum::http::{HeaderMap, Method, StatusCode};
 use jsonwebtoken::{self, Algorithm};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
-use tokio::sync::{RwLockReadGuard, RwLockWriteGuard};
+use std::{net::IpAddr, time::Duration};
+use tokio::sync::watch;
 
 #[derive(Clone)]
 // The main auth struct that will be used to authenticate, and authorise a user.
 pub struct Auth {
     pub enabled: bool,
-    pub key_id: String,
+    pub keys: watch::Receiver<KeyRing>,
     pub signing_method: Algorithm,
-    pub db: PgPool,
+    pub db: Database,
+    pub session: SessionConfig,
+    pub account: AccountConfig,
+    pub mfa: MfaConfig,
+    pub login: LoginConfig,
 }
 
 // The configuration when creating a new auth instance.
 pub struct AuthConfig {
     pub enabled: bool,
-    pub key_id: String,
+    // The keys are read from the channel, so they can be rotated while the application is running.
+    pub keys: watch::Receiver<KeyRing>,
     pub signing_method: Algorithm,
-    pub db: PgPool,
+    pub db: Database,
+    // Cookie based sessions, these are disabled by default.
+    pub session: SessionConfig,
+    // Email verification and password reset.
+    pub account: AccountConfig,
+    // Limits on the second step of an mfa login.
+    pub mfa: MfaConfig,
+    // Limits on the first step of a login.
+    pub login: LoginConfig,
+}
+
+// The configuration for logging in with a password. Each attempt runs argon2, so they must be limited to stop
+// passwords being guessed, and the service being tied up.
+#[derive(Clone)]
+pub struct LoginConfig {
+    // Limits how many logins can be attempted per email address.
+    pub email_limiter: Limiter,
+    // Limits how many logins can be attempted per client address, this is set higher as clients can share one.
+    pub address_limiter: Limiter,
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        LoginConfig {
+            email_limiter: limiter::new(limiter::Config {
+                max_attempts: 10,
+                window: Duration::from_secs(15 * 60),
+            }),
+            address_limiter: limiter::new(limiter::Config {
+                max_attempts: 100,
+                window: Duration::from_secs(15 * 60),
+            }),
+        }
+    }
+}
+
+// KeyRing holds the key new tokens are signed with, and the previous keys tokens are still accepted from. Keys are
+// rotated by signing with a new key, while the old one is kept as previous until its tokens have expired.
+// Each key is a key pair in scaffold/keys, private-{id}.pem and public-{id}.pem.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyRing {
+    pub signing: String,
+    pub previous: Vec<String>,
+}
+
+impl KeyRing {
+    // fn accepts() checks if tokens signed with the key are accepted.
+    pub fn accepts(&self, key_id: &str) -> bool {
+        self.signing == key_id || self.previous.iter().any(|previous| previous == key_id)
+    }
 }
 
 // The struct that contains all standard claims common within a JWT.
@@ -37,22 +105,96 @@ pub struct StandardClaims {
     pub sub: String,
 }
 
+// The claims of short lived tokens that can only be used for one purpose, which is set as the audience.
+// For example, the token returned after a password is verified, for users with mfa enabled.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PurposeClaims {
+    pub aud: String,
+    pub exp: u64,
+    pub iat: u64,
+    pub iss: String,
+    pub sub: String,
+    // The id of the token, used to make sure single use tokens are only used once.
+    #[serde(default)]
+    pub jti: String,
+}
+
+// The columns of a user checked during login, the hash is empty for users that can not log in with a password.
+#[derive(sqlx::FromRow)]
+struct LoginRow {
+    id: i32,
+    password_hash: Option<String>,
+    mfa_enabled: bool,
+}
+
+// The user returned from a successful password check.
+pub struct LoginUser {
+    pub id: i32,
+    // When enabled, the user must complete the second step before they are given an access token.
+    pub mfa_enabled: bool,
+}
+
+// The audiences of our tokens, so one kind of token can never be accepted as the other.
+pub const ACCESS_AUDIENCE: &str = "external-api";
+pub const MFA_AUDIENCE: &str = "external-api-mfa";
+pub const VERIFY_EMAIL_AUDIENCE: &str = "external-api-verify-email";
+pub const PASSWORD_RESET_AUDIENCE: &str = "external-api-password-reset";
+
+// The error codes a Bearer challenge can contain, as defined in RFC 6750.
+pub const INVALID_REQUEST: &str = "invalid_request";
+pub const INVALID_TOKEN: &str = "invalid_token";
+pub const INSUFFICIENT_SCOPE: &str = "insufficient_scope";
+
 pub fn new(config: AuthConfig) -> Auth {
     Auth {
         enabled: config.enabled,
-        key_id: config.key_id,
+        keys: config.keys,
         signing_method: config.signing_method,
-        db: config.db,
+        // Auth reads what it has just written, like a new session or password, so it never reads from a replica.
+        db: config.db.read_your_writes(),
+        session: config.session,
+        account: config.account,
+        mfa: config.mfa,
+        login: config.login,
+    }
+}
+
+// fn check_keys() loads every key of the key ring, the same way tokens are signed and validated, and returns why
+// each could not be loaded. This finds a missing, or unreadable key at start up, rather than on the first login.
+pub fn check_keys(keys: &KeyRing, signing_method: Algorithm) -> Vec<String> {
+    let mut problems = vec![];
+
+    if let Err(err) = encode::load_encoding_key(&keys.signing, signing_method) {
+        problems.push(format!(
+            "could not load the signing key {} : {}",
+            keys.signing, err.message
+        ));
     }
+
+    for key_id in std::iter::once(&keys.signing).chain(&keys.previous) {
+        if let Err(err) = decode::load_decoding_key(key_id, signing_method) {
+            problems.push(format!(
+                "could not load the validation key {} : {}",
+                key_id, err.message
+            ));
+        }
+    }
+
+    problems
 }
 
 impl Auth {
+    // fn signing_key() returns the id of the key new tokens are signed with.
+    fn signing_key(&self) -> String {
+        self.keys.borrow().signing.clone()
+    }
+
     // Creates a new JWT for the given user id (will be uuid). Used either to manually create a token
     // Or to return a new token on successful login.
     pub async fn new_token(&self, user_id: i32) -> Result<String, SystemError> {
         let data = match encode_token(
             user_id,
-            self.key_id.clone(),
+            self.signing_key(),
             self.signing_method,
             self.db.clone(),
         )
@@ -66,26 +208,415 @@ impl Auth {
     }
 
     // pub fn authenticate() Decodes and validates the incoming token, and if successful, maps and returns the claims.
-    pub fn authenticate(
+    pub fn authenticate(&self, token: String) -> Result<StandardClaims, SystemError> {
+        let data = match decode::validate_token(token, &self.keys.borrow(), self.signing_method) {
+            Ok(data) => data,
+            Err(err) => return Err(err),
+        };
+
+        Ok(data.claims)
+    }
+
+    // pub async fn login() checks the email and password against the users table, and returns the user id.
+    // The same error is returned for an unknown email and a wrong password, and both take as long to check, so
+    // emails cannot be enumerated. Attempts are limited per email, and per client address when it is known.
+    pub async fn login(
         &self,
-        token: String,
-        mutex: &mut RwLockWriteGuard<MuxState>,
-    ) -> Result<(), SystemError> {
-        let data = match decode::validate_token(token, &self.key_id, self.signing_method) {
+        email: &str,
+        password: &str,
+        address: Option<IpAddr>,
+    ) -> Result<LoginUser, SystemError> {
+        let email = account::normalise_email(email);
+
+        let mut limits = vec![(&self.login.email_limiter, email.clone())];
+        if let Some(address) = address {
+            limits.push((&self.login.address_limiter, address.to_string()));
+        }
+
+        for (limiter, key) in limits {
+            if let Err(retry_after) = limiter.check(&key) {
+                return Err(SystemError::new_too_many_requests(
+                    retry_after,
+                    format!(
+                        "too many login attempts, try again in {} seconds",
+                        retry_after.as_secs() + 1
+                    ),
+                ));
+            }
+        }
+
+        let query = "
+            SELECT id, password_hash, mfa_enabled
+            FROM users
+            WHERE lower(email) = $1";
+
+        let statement = sqlx::query(query).bind(email);
+
+        let rows =
+            match database::query_many_rows::<LoginRow>(&self.db, "users.select_login", statement)
+                .await
+            {
+                Ok(rows) => rows,
+                Err(err) => {
+                    return Err(SystemError::new(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("could not fetch user during login : {}", err),
+                    ))
+                }
+            };
+
+        let (user, hash) = match rows.into_iter().next() {
+            Some(row) => (
+                Some(LoginUser {
+                    id: row.id,
+                    mfa_enabled: row.mfa_enabled,
+                }),
+                row.password_hash,
+            ),
+            None => (None, None),
+        };
+
+        // Users without a password are checked against the dummy hash, like an unknown email.
+        let verified = match password::spawn_verify_password(password.to_string(), hash).await {
+            Ok(verified) => verified,
+            Err(err) => return Err(err),
+        };
+
+        match user {
+            Some(user) if verified => Ok(user),
+            _ => Err(SystemError::new_unauthorised(
+                bearer_challenge(Some(INVALID_REQUEST), Some("invalid email or password")),
+                "invalid email or password",
+            )),
+        }
+    }
+
+    // pub fn new_mfa_token() creates the token returned from the first step of an mfa login. Each token has its own
+    // id, so the wrong codes sent with it can be counted.
+    pub fn new_mfa_token(&self, user_id: i32) -> Result<String, SystemError> {
+        let jti = match random_token(16) {
+            Ok(jti) => jti,
+            Err(err) => return Err(err),
+        };
+
+        encode_purpose_token(
+            user_id,
+            MFA_AUDIENCE,
+            jti,
+            mfa::TOKEN_LIFETIME,
+            self.signing_key(),
+            self.signing_method,
+        )
+    }
+
+    // pub async fn verify_mfa() is the second step of an mfa login, the mfa token is only upgraded once a valid
+    // totp or recovery code has been provided. The user id is returned to then create an access token or session.
+    // The codes tried per user are rate limited, and the mfa token can no longer be used once too many wrong codes
+    // have been sent with it.
+    pub async fn verify_mfa(&self, mfa_token: &str, code: &str) -> Result<i32, SystemError> {
+        let data = match decode::validate_purpose_token(
+            mfa_token,
+            MFA_AUDIENCE,
+            &self.keys.borrow(),
+            self.signing_method,
+        ) {
             Ok(data) => data,
             Err(err) => return Err(err),
         };
 
-        // Save to claims from our mutex shared state.
-        mutex.claims = data.claims;
+        let user_id = match data.claims.sub.parse::<i32>() {
+            Ok(user_id) => user_id,
+            Err(err) => {
+                return Err(SystemError::new_unauthorised(
+                    bearer_challenge(Some(INVALID_TOKEN), Some("the mfa token is invalid")),
+                    format!("mfa token contains an invalid subject : {}", err),
+                ))
+            }
+        };
+
+        if data.claims.jti.is_empty() || self.mfa.failures.exhausted(&data.claims.jti) {
+            return Err(SystemError::new_unauthorised(
+                bearer_challenge(
+                    Some(INVALID_TOKEN),
+                    Some("the mfa token is no longer valid"),
+                ),
+                "too many invalid codes were sent with the mfa token, the user must log in again",
+            ));
+        }
+
+        if let Err(retry_after) = self.mfa.limiter.check(&user_id.to_string()) {
+            return Err(SystemError::new_too_many_requests(
+                retry_after,
+                format!(
+                    "too many verification attempts, try again in {} seconds",
+                    retry_after.as_secs() + 1
+                ),
+            ));
+        }
+
+        if let Err(err) = mfa::verify_code(&self.db, user_id, code).await {
+            // Only a wrong code counts towards invalidating the token, not a failure on our side.
+            if err.status_code == StatusCode::UNAUTHORIZED {
+                let _ = self.mfa.failures.check(&data.claims.jti);
+            }
+
+            return Err(err);
+        }
+
+        Ok(user_id)
+    }
+
+    // pub async fn begin_mfa_enrollment() creates a new totp secret for the user.
+    pub async fn begin_mfa_enrollment(&self, user_id: i32) -> Result<Enrollment, SystemError> {
+        mfa::begin_enrollment(&self.db, user_id).await
+    }
+
+    // pub async fn confirm_mfa_enrollment() enables mfa for the user, and returns their recovery codes.
+    pub async fn confirm_mfa_enrollment(
+        &self,
+        user_id: i32,
+        code: &str,
+    ) -> Result<Vec<String>, SystemError> {
+        mfa::confirm_enrollment(&self.db, user_id, code).await
+    }
+
+    // pub async fn new_session() creates a server side session for the user, to be returned as cookies.
+    pub async fn new_session(&self, user_id: i32) -> Result<NewSession, SystemError> {
+        session::create_session(&self.db, &self.session, user_id).await
+    }
+
+    // pub async fn end_session() removes the server side session.
+    pub async fn end_session(&self, id: &str) -> Result<(), SystemError> {
+        session::delete_session(&self.db, id).await
+    }
+
+    // pub async fn authenticate_session() loads the session and maps the claims. Unsafe methods must also
+    // pass the csrf check, as the browser sends our cookies regardless of which site made the request.
+    pub async fn authenticate_session(
+        &self,
+        id: &str,
+        method: &Method,
+        headers: &HeaderMap,
+    ) -> Result<StandardClaims, SystemError> {
+        let session = match session::load_session(&self.db, &self.session, id).await {
+            Ok(session) => session,
+            Err(err) => return Err(err),
+        };
+
+        let safe = matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        );
+
+        if !safe {
+            if let Err(err) = session::verify_csrf(headers, &session) {
+                return Err(err);
+            }
+        }
+
+        Ok(session.claims)
+    }
+
+    // pub async fn request_email_verification() emails the user a link to verify their email address. Nothing is
+    // returned to say whether the account exists, so the endpoint cannot be used to enumerate emails.
+    pub async fn request_email_verification(&self, email: &str) -> Result<(), SystemError> {
+        let user = match self.find_account(email).await {
+            Ok(Some(user)) if !user.email_verified => user,
+            Ok(_) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let token = match self
+            .new_account_token(
+                user.id,
+                account::VERIFY_EMAIL,
+                VERIFY_EMAIL_AUDIENCE,
+                self.account.verify_email_lifetime,
+            )
+            .await
+        {
+            Ok(token) => token,
+            Err(err) => return Err(err),
+        };
+
+        self.send_mail(Mail {
+            to: user.email,
+            subject: String::from("Verify your email address"),
+            body: format!(
+                "Please verify your email address by following the link below.\n\n{}/verify-email?token={}\n",
+                self.account.link_url, token
+            ),
+        });
+
+        Ok(())
+    }
+
+    // pub async fn confirm_email_verification() checks the token from the email, and marks the email as verified.
+    pub async fn confirm_email_verification(&self, token: &str) -> Result<(), SystemError> {
+        let user_id = match self
+            .use_account_token(token, account::VERIFY_EMAIL, VERIFY_EMAIL_AUDIENCE)
+            .await
+        {
+            Ok(user_id) => user_id,
+            Err(err) => return Err(err),
+        };
+
+        account::mark_email_verified(&self.db, user_id).await
+    }
+
+    // pub async fn request_password_reset() emails the user a link to reset their password. As with verification,
+    // the same result is returned whether the account exists or not.
+    pub async fn request_password_reset(&self, email: &str) -> Result<(), SystemError> {
+        let user = match self.find_account(email).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let token = match self
+            .new_account_token(
+                user.id,
+                account::PASSWORD_RESET,
+                PASSWORD_RESET_AUDIENCE,
+                self.account.password_reset_lifetime,
+            )
+            .await
+        {
+            Ok(token) => token,
+            Err(err) => return Err(err),
+        };
+
+        self.send_mail(Mail {
+            to: user.email,
+            subject: String::from("Reset your password"),
+            body: format!(
+                "A password reset was requested for your account. If this was not you, you can ignore this email.\n\n{}/password-reset?token={}\n",
+                self.account.link_url, token
+            ),
+        });
 
         Ok(())
     }
 
+    // pub async fn confirm_password_reset() checks the token from the email, and sets the new password. Every
+    // session is ended, so the user must log in again with the new password.
+    pub async fn confirm_password_reset(
+        &self,
+        token: &str,
+        password: &str,
+    ) -> Result<(), SystemError> {
+        // The token is only consumed together with the password update, so it is read here without being used.
+        let (user_id, jti) = match self.read_account_token(token, PASSWORD_RESET_AUDIENCE) {
+            Ok(token) => token,
+            Err(err) => return Err(err),
+        };
+
+        let password_hash = match password::spawn_hash_password(password.to_string()).await {
+            Ok(password_hash) => password_hash,
+            Err(err) => return Err(err),
+        };
+
+        account::reset_password(&self.db, &jti, user_id, password_hash).await
+    }
+
+    // fn find_account() applies the rate limit for the address before looking up the user.
+    async fn find_account(&self, email: &str) -> Result<Option<account::AccountUser>, SystemError> {
+        let email = account::normalise_email(email);
+
+        if let Err(retry_after) = self.account.limiter.check(&email) {
+            return Err(SystemError::new_too_many_requests(
+                retry_after,
+                format!(
+                    "too many requests for this address, try again in {} seconds",
+                    retry_after.as_secs() + 1
+                ),
+            ));
+        }
+
+        account::find_user(&self.db, &email).await
+    }
+
+    // fn new_account_token() stores a single use token, and signs its id into a token for the given audience.
+    async fn new_account_token(
+        &self,
+        user_id: i32,
+        purpose: &str,
+        audience: &str,
+        lifetime: Duration,
+    ) -> Result<String, SystemError> {
+        let jti = match account::store_token(&self.db, user_id, purpose, lifetime).await {
+            Ok(jti) => jti,
+            Err(err) => return Err(err),
+        };
+
+        encode_purpose_token(
+            user_id,
+            audience,
+            jti,
+            lifetime,
+            self.signing_key(),
+            self.signing_method,
+        )
+    }
+
+    // fn use_account_token() validates the signed token, and then consumes the stored token so it cannot be reused.
+    async fn use_account_token(
+        &self,
+        token: &str,
+        purpose: &str,
+        audience: &str,
+    ) -> Result<i32, SystemError> {
+        let (user_id, jti) = match self.read_account_token(token, audience) {
+            Ok(token) => token,
+            Err(err) => return Err(err),
+        };
+
+        if let Err(err) = account::consume_token(&self.db, &jti, user_id, purpose).await {
+            return Err(err);
+        }
+
+        Ok(user_id)
+    }
+
+    // fn read_account_token() validates the signed token, and returns the user id and the id of the stored token.
+    fn read_account_token(
+        &self,
+        token: &str,
+        audience: &str,
+    ) -> Result<(i32, String), SystemError> {
+        let data = match decode::validate_purpose_token(
+            token,
+            audience,
+            &self.keys.borrow(),
+            self.signing_method,
+        ) {
+            Ok(data) => data,
+            Err(err) => return Err(err),
+        };
+
+        let user_id = match data.claims.sub.parse::<i32>() {
+            Ok(user_id) => user_id,
+            Err(err) => {
+                return Err(SystemError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("token contains an invalid subject : {}", err),
+                ))
+            }
+        };
+
+        Ok((user_id, data.claims.jti))
+    }
+
+    // fn send_mail() sends the email in the background, so the response time does not reveal whether an email
+    // was sent, and therefore whether the account exists.
+    fn send_mail(&self, mail: Mail) {
+        self.account.mailer.send_in_background(mail);
+    }
+
     // pub fn authorise() checks the claims to verify if they contain the information we would like them to contain.
     pub fn authorise(
         &self,
-        mutex: &RwLockReadGuard<MuxState>,
+        claims: &StandardClaims,
         roles: Option<Vec<String>>,
     ) -> Result<(), SystemError> {
         // Very Basic for now.
@@ -98,9 +629,10 @@ impl Auth {
             }
         };
 
-        if !list.contains(&mutex.claims.role) {
-            return Err(SystemError::new(
-                StatusCode::UNAUTHORIZED,
+        // The user is authenticated at this point, so a missing role is a 403 and not a 401.
+        if !list.contains(&claims.role) {
+            return Err(SystemError::new_forbidden(
+                bearer_challenge(Some(INSUFFICIENT_SCOPE), None),
                 "you are not authorised for this resource",
             ));
         }
@@ -109,6 +641,28 @@ impl Auth {
     }
 }
 
+// pub fn bearer_challenge() builds the value of a WWW-Authenticate header for the Bearer scheme.
+// No error code should be given when the request simply did not contain any credentials.
+pub fn bearer_challenge(error: Option<&str>, description: Option<&str>) -> String {
+    let mut params = vec![];
+
+    if let Some(error) = error {
+        params.push(format!("error=\"{}\"", error));
+    }
+
+    // Quotes and backslashes are not allowed within a quoted string, so we strip them.
+    if let Some(description) = description {
+        let description = description.replace(['"', '\\'], "");
+        params.push(format!("error_description=\"{}\"", description));
+    }
+
+    if params.is_empty() {
+        return String::from("Bearer");
+    }
+
+    format!("Bearer {}", params.join(", "))
+}
+
 impl StandardClaims {
     // Has the Exp claim expired?
     pub fn has_expired() {
@@ -122,3 +676,29 @@ impl StandardClaims {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bearer_challenge_without_an_error() {
+        assert_eq!(bearer_challenge(None, None), "Bearer");
+    }
+
+    #[test]
+    fn bearer_challenge_with_an_error_and_description() {
+        assert_eq!(
+            bearer_challenge(Some(INVALID_TOKEN), Some("the token has expired")),
+            "Bearer error=\"invalid_token\", error_description=\"the token has expired\""
+        );
+    }
+
+    #[test]
+    fn bearer_challenge_strips_quotes_and_backslashes() {
+        assert_eq!(
+            bearer_challenge(Some(INVALID_REQUEST), Some("a \"quoted\" \\ value")),
+            "Bearer error=\"invalid_request\", error_description=\"a quoted  value\""
+        );
+    }
+}
@@ -1,7 +1,9 @@
-use super::auth::StandardClaims;
+use super::auth::{
+    bearer_challenge, KeyRing, PurposeClaims, StandardClaims, ACCESS_AUDIENCE, INVALID_TOKEN,
+};
 use crate::domain::system::error::error::SystemError;
 use axum::http::StatusCode;
-use jsonwebtoken::{self, Algorithm, DecodingKey, TokenData, Validation};
+use jsonwebtoken::{self, errors::ErrorKind, Algorithm, DecodingKey, TokenData, Validation};
 use std::{env, fs, io::Read, path::PathBuf};
 
 /// Decode abstracts away the logic that performs the decoding, and validation of a JWT.
@@ -10,28 +12,108 @@ use std::{env, fs, io::Read, path::PathBuf};
 // If the JWT is not valid, or the public key is incorrect, then we simply return an error.
 pub fn validate_token(
     token: String,
-    key_id: &String,
+    keys: &KeyRing,
     signing_method: Algorithm,
 ) -> Result<TokenData<StandardClaims>, SystemError> {
+    // The token names the key it was signed with, which must still be in the key ring.
+    let key_id = match signed_with(&token, keys) {
+        Ok(key_id) => key_id,
+        Err(err) => return Err(err),
+    };
+
     // We obtain the relevant decoding key (private.pem for RSA256 etc)
     let key = match load_decoding_key(&key_id, signing_method) {
         Ok(key) => key,
         Err(err) => return Err(err),
     };
 
+    // Only access tokens are accepted here, so an mfa token can never be used in place of one.
+    let mut validation = Validation::new(signing_method);
+    validation.set_audience(&[ACCESS_AUDIENCE]);
+
     // We then use that decoding key on the incoming token to validate its legitimacy, if so, then we map the token
     // to the claims.
-    let data: TokenData<StandardClaims> =
-        match jsonwebtoken::decode(&token, &key, &Validation::new(signing_method)) {
-            Ok(data) => data,
-            Err(err) => return Err(SystemError::new(StatusCode::UNAUTHORIZED, err.to_string())),
-        };
+    let data: TokenData<StandardClaims> = match jsonwebtoken::decode(&token, &key, &validation) {
+        Ok(data) => data,
+        Err(err) => {
+            // The client is told why the token was rejected, the underlying error is only logged.
+            let description = match err.kind() {
+                ErrorKind::ExpiredSignature => "the access token has expired",
+                _ => "the access token is invalid",
+            };
+            return Err(SystemError::new_unauthorised(
+                bearer_challenge(Some(INVALID_TOKEN), Some(description)),
+                err.to_string(),
+            ));
+        }
+    };
 
     Ok(data)
 }
 
+// pub fn validate_purpose_token() checks the token was issued for the given purpose (audience).
+pub fn validate_purpose_token(
+    token: &str,
+    audience: &str,
+    keys: &KeyRing,
+    signing_method: Algorithm,
+) -> Result<TokenData<PurposeClaims>, SystemError> {
+    let key_id = match signed_with(token, keys) {
+        Ok(key_id) => key_id,
+        Err(err) => return Err(err),
+    };
+
+    let key = match load_decoding_key(&key_id, signing_method) {
+        Ok(key) => key,
+        Err(err) => return Err(err),
+    };
+
+    let mut validation = Validation::new(signing_method);
+    validation.set_audience(&[audience]);
+
+    match jsonwebtoken::decode(token, &key, &validation) {
+        Ok(data) => Ok(data),
+        Err(err) => Err(SystemError::new_unauthorised(
+            bearer_challenge(
+                Some(INVALID_TOKEN),
+                Some("the token is invalid or has expired"),
+            ),
+            err.to_string(),
+        )),
+    }
+}
+
+// fn signed_with() returns the id of the key the token was signed with, when it is in the key ring. Tokens that do not
+// name their key were signed before keys could be rotated, so are checked against the signing key.
+fn signed_with(token: &str, keys: &KeyRing) -> Result<String, SystemError> {
+    let header = match jsonwebtoken::decode_header(token) {
+        Ok(header) => header,
+        Err(err) => {
+            return Err(SystemError::new_unauthorised(
+                bearer_challenge(Some(INVALID_TOKEN), Some("the token is invalid")),
+                err.to_string(),
+            ))
+        }
+    };
+
+    match header.kid {
+        None => Ok(keys.signing.clone()),
+        Some(key_id) if keys.accepts(&key_id) => Ok(key_id),
+        Some(key_id) => Err(SystemError::new_unauthorised(
+            bearer_challenge(Some(INVALID_TOKEN), Some("the token is invalid")),
+            format!(
+                "the token was signed with a key that is no longer accepted : {}",
+                key_id
+            ),
+        )),
+    }
+}
+
 // fn load_decoding_key() loads the correct public key or secret based on the signing method passed in.
-fn load_decoding_key(key_id: &str, signing_method: Algorithm) -> Result<DecodingKey, SystemError> {
+pub(super) fn load_decoding_key(
+    key_id: &str,
+    signing_method: Algorithm,
+) -> Result<DecodingKey, SystemError> {
     // Based on the signing method, we load a different key for our project.
     let key = match signing_method {
         Algorithm::HS256 => DecodingKey::from_secret("secret".as_bytes()),
@@ -1,8 +1,10 @@
-use super::auth::StandardClaims;
-use crate::{domain::system::error::error::SystemError, lib::database::database};
+use super::auth::{PurposeClaims, StandardClaims, ACCESS_AUDIENCE};
+use crate::{
+    domain::system::error::error::SystemError,
+    lib::database::database::{self, Database},
+};
 use axum::http::StatusCode;
 use jsonwebtoken::{Algorithm, EncodingKey, Header};
-use sqlx::{PgPool, Row};
 use std::{
     env, fs,
     io::Read,
@@ -10,12 +12,21 @@ use std::{
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+// The user details signed into the claims of an access token.
+#[derive(sqlx::FromRow)]
+struct ClaimsUser {
+    email: String,
+    first_name: String,
+    last_name: String,
+    role: String,
+}
+
 // pub async fn encode_token() creates a new token based on the encoding method passed.
 pub async fn encode_token(
     user_id: i32,
     key_id: String,
     signing_method: Algorithm,
-    db: PgPool,
+    db: Database,
 ) -> Result<String, SystemError> {
     // Load the correct encoding key based on the encoding algorithm provided.
     let (mut header, key) = match load_encoding_key(&key_id, signing_method) {
@@ -40,8 +51,10 @@ pub async fn encode_token(
     let statement = sqlx::query(query).bind(user_id);
 
     // Fetch a single row of a user by using fn query_single_row()
-    let row = match database::query_single_row(&db, statement).await {
-        Ok(rows) => rows,
+    let user = match database::query_single_row::<ClaimsUser>(&db, "users.select_claims", statement)
+        .await
+    {
+        Ok(user) => user,
         Err(err) => {
             return Err(SystemError::new(
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -55,13 +68,13 @@ pub async fn encode_token(
 
     // Create out new standard claims object.
     let standard_claims = StandardClaims {
-        email: row.get("email"),
-        first_name: row.get("first_name"),
-        last_name: row.get("last_name"),
-        role: row.get("role"),
-        aud: String::from("external-api"),
+        email: user.email,
+        first_name: user.first_name,
+        last_name: user.last_name,
+        role: user.role,
+        aud: String::from(ACCESS_AUDIENCE),
         iss: String::from("external-api"),
-        sub: String::from("10"), // we should get this from the user uuid in the db.
+        sub: user_id.to_string(), // we should get this from the user uuid in the db.
         iat: issued_at,
         exp: expires_at,
     };
@@ -83,8 +96,50 @@ pub async fn encode_token(
     Ok(new_token)
 }
 
+// pub fn encode_purpose_token() creates a short lived token that can only be used for one purpose, such as the second
+// step of an mfa login, or resetting a password. It does not contain any claims used for authorisation, and the
+// audience is checked when decoding, so it can never be used in place of an access token.
+pub fn encode_purpose_token(
+    user_id: i32,
+    audience: &str,
+    jti: String,
+    lifetime: Duration,
+    key_id: String,
+    signing_method: Algorithm,
+) -> Result<String, SystemError> {
+    let (mut header, key) = match load_encoding_key(&key_id, signing_method) {
+        Ok((header, key)) => (header, key),
+        Err(err) => return Err(err),
+    };
+
+    let issued_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let expires_at = issued_at + lifetime.as_secs();
+
+    header.kid = Some(key_id);
+
+    let purpose_claims = PurposeClaims {
+        aud: String::from(audience),
+        iss: String::from("external-api"),
+        sub: user_id.to_string(),
+        jti,
+        iat: issued_at,
+        exp: expires_at,
+    };
+
+    match jsonwebtoken::encode(&header, &purpose_claims, &key) {
+        Ok(new_token) => Ok(new_token),
+        Err(err) => Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to encode new {} token : {}", audience, err),
+        )),
+    }
+}
+
 // fn load_encoding_key() loads the correct encoding from the project based on the algorithm.
-fn load_encoding_key(
+pub(super) fn load_encoding_key(
     key_id: &str,
     signing_method: Algorithm,
 ) -> Result<(Header, EncodingKey), SystemError> {
@@ -0,0 +1,325 @@
+use super::{
+    auth::{bearer_challenge, INVALID_REQUEST},
+    session::{hash_token, random_token},
+    totp,
+};
+use crate::{
+    domain::system::error::error::SystemError,
+    lib::{
+        database::database::{self, Database},
+        limiter::limiter::{self, Limiter},
+    },
+};
+use axum::http::StatusCode;
+use std::time::Duration;
+
+// Mfa abstracts away the storage of totp secrets and recovery codes for multi-factor authentication.
+
+// The issuer shown to the user within their authenticator app.
+pub const ISSUER: &str = "external-api";
+// The number of recovery codes given to the user once enrollment is confirmed.
+const RECOVERY_CODES: usize = 10;
+// How long the user has to complete the second step of an mfa login.
+pub const TOKEN_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+// The configuration for the second step of an mfa login. Codes are short, so the number of guesses must be limited.
+#[derive(Clone)]
+pub struct MfaConfig {
+    // Limits how many codes can be tried per user, across every mfa token.
+    pub limiter: Limiter,
+    // Limits how many wrong codes can be sent with one mfa token, before the user has to log in again.
+    pub failures: Limiter,
+}
+
+impl Default for MfaConfig {
+    fn default() -> Self {
+        MfaConfig {
+            limiter: limiter::new(limiter::Config {
+                max_attempts: 5,
+                window: Duration::from_secs(15 * 60),
+            }),
+            // The window covers the whole lifetime of the token.
+            failures: limiter::new(limiter::Config {
+                max_attempts: 3,
+                window: TOKEN_LIFETIME,
+            }),
+        }
+    }
+}
+
+// The details returned to the user to add the secret to their authenticator app.
+pub struct Enrollment {
+    pub secret: String,
+    pub uri: String,
+}
+
+// pub async fn begin_enrollment() creates a new secret for the user. The secret is not used to log in until
+// the user has confirmed they can generate codes from it.
+pub async fn begin_enrollment(db: &Database, user_id: i32) -> Result<Enrollment, SystemError> {
+    let secret = match totp::new_secret() {
+        Ok(secret) => secret,
+        Err(err) => return Err(err),
+    };
+
+    let query = "
+        UPDATE users SET mfa_secret = $1, mfa_last_step = NULL
+        WHERE id = $2 AND mfa_enabled = false
+        RETURNING email";
+
+    let statement = sqlx::query(query).bind(&secret).bind(user_id);
+
+    // The columns are mapped in the order they are returned.
+    let rows = match database::query_many_rows::<(String,)>(
+        db,
+        "users.update_mfa_secret",
+        statement,
+    )
+    .await
+    {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not store mfa secret : {}", err),
+            ))
+        }
+    };
+
+    let email = match rows.into_iter().next() {
+        Some((email,)) => email,
+        None => {
+            return Err(SystemError::new(
+                StatusCode::CONFLICT,
+                "multi-factor authentication is already enabled",
+            ))
+        }
+    };
+
+    Ok(Enrollment {
+        uri: totp::provisioning_uri(&secret, ISSUER, &email),
+        secret,
+    })
+}
+
+// pub async fn confirm_enrollment() enables mfa once the user has sent a valid code, and returns their recovery
+// codes. Recovery codes are stored hashed, so this is the only time they can be seen.
+pub async fn confirm_enrollment(
+    db: &Database,
+    user_id: i32,
+    code: &str,
+) -> Result<Vec<String>, SystemError> {
+    let query = "
+        SELECT mfa_secret
+        FROM users
+        WHERE id = $1 AND mfa_enabled = false AND mfa_secret IS NOT NULL";
+
+    let statement = sqlx::query(query).bind(user_id);
+
+    let rows = match database::query_many_rows::<(String,)>(
+        db,
+        "users.select_mfa_secret",
+        statement,
+    )
+    .await
+    {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not load mfa secret : {}", err),
+            ))
+        }
+    };
+
+    let secret = match rows.into_iter().next() {
+        Some((secret,)) => secret,
+        None => {
+            return Err(SystemError::new(
+                StatusCode::CONFLICT,
+                "multi-factor authentication enrollment has not been started",
+            ))
+        }
+    };
+
+    let step = match totp::verify(&secret, code, None) {
+        Ok(Some(step)) => step,
+        Ok(None) => return Err(invalid_code()),
+        Err(err) => return Err(err),
+    };
+
+    // The codes are created first, so nothing can fail between the statements but the database.
+    let mut codes = vec![];
+
+    for _ in 0..RECOVERY_CODES {
+        match random_token(5) {
+            Ok(code) => codes.push(format!("{}-{}", &code[..5], &code[5..])),
+            Err(err) => return Err(err),
+        };
+    }
+
+    // Mfa is only enabled together with every recovery code, so the user can never be left with mfa enabled and
+    // codes they have not been given. Returning before the commit rolls back the transaction.
+    let mut transaction = match database::begin_write(db).await {
+        Ok(transaction) => transaction,
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not enable mfa : {}", err),
+            ))
+        }
+    };
+
+    let query = "
+        UPDATE users SET mfa_enabled = true, mfa_last_step = $1
+        WHERE id = $2 AND mfa_enabled = false";
+    let statement = sqlx::query(query).bind(step as i64).bind(user_id);
+
+    match database::mutate_in_transaction(
+        db,
+        &mut transaction,
+        "users.update_mfa_enabled",
+        statement,
+    )
+    .await
+    {
+        Ok(1) => {}
+        // Another request confirmed the enrollment first.
+        Ok(_) => {
+            return Err(SystemError::new(
+                StatusCode::CONFLICT,
+                "multi-factor authentication is already enabled",
+            ))
+        }
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not enable mfa : {}", err),
+            ))
+        }
+    };
+
+    // Any codes from a previous enrollment can no longer be used.
+    let statement = sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1").bind(user_id);
+
+    if let Err(err) =
+        database::mutate_in_transaction(db, &mut transaction, "recovery_codes.delete", statement)
+            .await
+    {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not remove old recovery codes : {}", err),
+        ));
+    }
+
+    for code in &codes {
+        let query = "INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)";
+        let statement = sqlx::query(query).bind(user_id).bind(hash_token(code));
+
+        if let Err(err) = database::mutate_in_transaction(
+            db,
+            &mut transaction,
+            "recovery_codes.insert",
+            statement,
+        )
+        .await
+        {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not store recovery code : {}", err),
+            ));
+        }
+    }
+
+    if let Err(err) = transaction.commit().await {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not enable mfa : {}", err),
+        ));
+    }
+
+    Ok(codes)
+}
+
+// pub async fn verify_code() checks either a totp code, or an unused recovery code for the user. Each code can
+// only be used once.
+pub async fn verify_code(db: &Database, user_id: i32, code: &str) -> Result<(), SystemError> {
+    let code = code.trim();
+
+    // Totp codes are always digits, so anything else can only be a recovery code.
+    if code.len() != totp::DIGITS as usize || !code.chars().all(|char| char.is_ascii_digit()) {
+        return use_recovery_code(db, user_id, code).await;
+    }
+
+    let query = "
+        SELECT mfa_secret, mfa_last_step
+        FROM users
+        WHERE id = $1 AND mfa_enabled = true";
+
+    let statement = sqlx::query(query).bind(user_id);
+
+    let rows =
+        match database::query_many_rows::<(String, Option<i64>)>(db, "users.select_mfa", statement)
+            .await
+        {
+            Ok(rows) => rows,
+            Err(err) => {
+                return Err(SystemError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("could not load mfa secret : {}", err),
+                ))
+            }
+        };
+
+    let (secret, last_step) = match rows.into_iter().next() {
+        Some(row) => row,
+        None => return Err(invalid_code()),
+    };
+
+    let step = match totp::verify(&secret, code, last_step) {
+        Ok(Some(step)) => step,
+        Ok(None) => return Err(invalid_code()),
+        Err(err) => return Err(err),
+    };
+
+    // We only move the step forward, so a concurrent request with the same code cannot also succeed.
+    let query = "
+        UPDATE users SET mfa_last_step = $1
+        WHERE id = $2 AND (mfa_last_step IS NULL OR mfa_last_step < $1)";
+
+    let statement = sqlx::query(query).bind(step as i64).bind(user_id);
+
+    match database::mutate_statement(db, "users.update_mfa_last_step", statement).await {
+        Ok(1) => Ok(()),
+        Ok(_) => Err(invalid_code()),
+        Err(err) => Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not store mfa step : {}", err),
+        )),
+    }
+}
+
+async fn use_recovery_code(db: &Database, user_id: i32, code: &str) -> Result<(), SystemError> {
+    let query = "
+        UPDATE recovery_codes SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL";
+
+    let statement = sqlx::query(query)
+        .bind(user_id)
+        .bind(hash_token(&code.to_lowercase()));
+
+    match database::mutate_statement(db, "recovery_codes.use", statement).await {
+        Ok(1) => Ok(()),
+        Ok(_) => Err(invalid_code()),
+        Err(err) => Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not use recovery code : {}", err),
+        )),
+    }
+}
+
+fn invalid_code() -> SystemError {
+    SystemError::new_unauthorised(
+        bearer_challenge(Some(INVALID_REQUEST), Some("invalid verification code")),
+        "invalid verification code",
+    )
+}
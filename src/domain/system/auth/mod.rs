@@ -1,3 +1,8 @@
+pub mod account;
 pub mod auth;
 pub mod decode;
 pub mod encode;
+pub mod mfa;
+pub mod password;
+pub mod session;
+pub mod totp;
@@ -0,0 +1,119 @@
+use crate::domain::system::error::error::SystemError;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::http::StatusCode;
+use std::sync::OnceLock;
+
+// Password abstracts away the hashing, and verification of user passwords.
+
+// pub fn hash_password() hashes a plain text password using argon2 with a random salt.
+// The returned string is in the PHC format, so it contains everything required to verify it later.
+pub fn hash_password(password: &str) -> Result<String, SystemError> {
+    let mut salt = [0u8; 16];
+    if let Err(err) = openssl::rand::rand_bytes(&mut salt) {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not generate password salt : {}", err),
+        ));
+    }
+
+    let salt = match SaltString::encode_b64(&salt) {
+        Ok(salt) => salt,
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not encode password salt : {}", err),
+            ))
+        }
+    };
+
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(err) => Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not hash password : {}", err),
+        )),
+    }
+}
+
+// pub fn verify_password() checks the plain text password against a hash created by hash_password().
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let hash = match PasswordHash::new(hash) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+// The hash checked when there is no user to check against, it is created once with the same parameters as every
+// other hash, so it always costs the same to verify.
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+// pub fn init_dummy_hash() creates the hash checked when there is no user, this is called at start up so a failure
+// stops the service rather than the first login.
+pub fn init_dummy_hash() -> Result<(), SystemError> {
+    match dummy_hash() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+// pub async fn spawn_hash_password() runs hash_password() on the blocking thread pool, argon2 is slow on purpose
+// and would otherwise hold up every other request on the same worker.
+pub async fn spawn_hash_password(password: String) -> Result<String, SystemError> {
+    match tokio::task::spawn_blocking(move || hash_password(&password)).await {
+        Ok(result) => result,
+        Err(err) => Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not hash password : {}", err),
+        )),
+    }
+}
+
+// pub async fn spawn_verify_password() runs verify_password() on the blocking thread pool. Without a hash, the dummy
+// hash is checked instead and false is returned, so an unknown user takes as long as a wrong password and the
+// response time does not reveal which accounts exist.
+pub async fn spawn_verify_password(
+    password: String,
+    hash: Option<String>,
+) -> Result<bool, SystemError> {
+    // We fail closed, without the dummy hash an unknown user would be answered straight away.
+    let dummy = match dummy_hash() {
+        Ok(dummy) => dummy,
+        Err(err) => return Err(err),
+    };
+
+    let verify = move || match &hash {
+        Some(hash) => verify_password(&password, hash),
+        None => {
+            verify_password(&password, dummy);
+            false
+        }
+    };
+
+    match tokio::task::spawn_blocking(verify).await {
+        Ok(verified) => Ok(verified),
+        Err(err) => Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not verify password : {}", err),
+        )),
+    }
+}
+
+fn dummy_hash() -> Result<&'static str, SystemError> {
+    if let Some(hash) = DUMMY_HASH.get() {
+        return Ok(hash);
+    }
+
+    let hash = match hash_password("dummy password") {
+        Ok(hash) => hash,
+        Err(err) => return Err(err),
+    };
+
+    Ok(DUMMY_HASH.get_or_init(|| hash))
+}
@@ -0,0 +1,376 @@
+use super::auth::{bearer_challenge, StandardClaims, INVALID_TOKEN};
+use crate::{
+    domain::system::error::error::SystemError,
+    lib::database::database::{self, Database},
+};
+use axum::http::{header, HeaderMap, StatusCode};
+use std::time::Duration;
+
+// Session abstracts away the logic for cookie based browser sessions, that are stored server side in postgres.
+
+// The cookie that contains the session id, this is HttpOnly so it can never be read by scripts.
+pub const SESSION_COOKIE: &str = "session_id";
+// The cookie that contains the csrf token, this is readable by the frontend so it can be sent back as a header.
+pub const CSRF_COOKIE: &str = "csrf_token";
+// The header unsafe requests must send the csrf token in.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+// The configuration for our session mode.
+#[derive(Clone)]
+pub struct SessionConfig {
+    pub enabled: bool,
+    // How long a session can go unused before it expires.
+    pub idle_timeout: Duration,
+    // How long a session can live for, regardless of use.
+    pub absolute_timeout: Duration,
+    // Secure should only be turned off when running locally over plain http.
+    pub secure: bool,
+    // One of Strict, Lax or None.
+    pub same_site: String,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            enabled: false,
+            idle_timeout: Duration::from_secs(30 * 60),
+            absolute_timeout: Duration::from_secs(12 * 60 * 60),
+            secure: true,
+            same_site: String::from("Strict"),
+        }
+    }
+}
+
+// A new session, the id and csrf token are only ever returned here, as we store the id hashed.
+pub struct NewSession {
+    pub id: String,
+    pub csrf_token: String,
+}
+
+// A session that has been loaded from the database.
+pub struct Session {
+    pub user_id: i32,
+    pub csrf_token: String,
+    pub claims: StandardClaims,
+}
+
+// The columns of a session, and its user, as they are loaded.
+#[derive(sqlx::FromRow)]
+struct SessionRow {
+    user_id: i32,
+    csrf_token: String,
+    email: String,
+    first_name: String,
+    last_name: String,
+    role: String,
+    issued_at: i64,
+    expires_at: i64,
+}
+
+// pub async fn create_session() stores a new session for the user, and returns the values to be set as cookies.
+pub async fn create_session(
+    db: &Database,
+    config: &SessionConfig,
+    user_id: i32,
+) -> Result<NewSession, SystemError> {
+    let session = NewSession {
+        id: match random_token(32) {
+            Ok(id) => id,
+            Err(err) => return Err(err),
+        },
+        csrf_token: match random_token(32) {
+            Ok(csrf_token) => csrf_token,
+            Err(err) => return Err(err),
+        },
+    };
+
+    // We take this opportunity to clean up any sessions that can no longer be used.
+    let query = "
+        DELETE FROM sessions
+        WHERE expires_at < now() OR last_seen_at < now() - make_interval(secs => $1)";
+
+    let statement = sqlx::query(query).bind(config.idle_timeout.as_secs() as f64);
+
+    if let Err(err) = database::mutate_statement(db, "sessions.delete_expired", statement).await {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not remove expired sessions : {}", err),
+        ));
+    }
+
+    let query = "
+        INSERT INTO sessions (id, user_id, csrf_token, expires_at)
+        VALUES ($1, $2, $3, now() + make_interval(secs => $4))";
+
+    let statement = sqlx::query(query)
+        .bind(hash_token(&session.id))
+        .bind(user_id)
+        .bind(&session.csrf_token)
+        .bind(config.absolute_timeout.as_secs() as f64);
+
+    if let Err(err) = database::mutate_statement(db, "sessions.insert", statement).await {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not create session : {}", err),
+        ));
+    }
+
+    Ok(session)
+}
+
+// pub async fn load_session() fetches a session that has not passed its idle or absolute timeout, and then
+// refreshes when it was last seen.
+pub async fn load_session(
+    db: &Database,
+    config: &SessionConfig,
+    id: &str,
+) -> Result<Session, SystemError> {
+    let query = "
+        SELECT s.user_id, s.csrf_token, u.email, u.first_name, u.last_name, u.role,
+            EXTRACT(EPOCH FROM s.created_at)::bigint AS issued_at,
+            EXTRACT(EPOCH FROM s.expires_at)::bigint AS expires_at
+        FROM sessions s
+        JOIN users u ON u.id = s.user_id
+        WHERE s.id = $1
+            AND s.expires_at > now()
+            AND s.last_seen_at > now() - make_interval(secs => $2)";
+
+    let statement = sqlx::query(query)
+        .bind(hash_token(id))
+        .bind(config.idle_timeout.as_secs() as f64);
+
+    let rows = match database::query_many_rows::<SessionRow>(db, "sessions.select", statement).await
+    {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not load session : {}", err),
+            ))
+        }
+    };
+
+    let row = match rows.into_iter().next() {
+        Some(row) => row,
+        None => {
+            return Err(SystemError::new_unauthorised(
+                bearer_challenge(Some(INVALID_TOKEN), Some("the session has expired")),
+                "session does not exist or has expired",
+            ))
+        }
+    };
+
+    let session = Session {
+        user_id: row.user_id,
+        csrf_token: row.csrf_token,
+        claims: StandardClaims {
+            email: row.email,
+            first_name: row.first_name,
+            last_name: row.last_name,
+            role: row.role,
+            aud: String::from("external-api"),
+            iss: String::from("external-api"),
+            sub: row.user_id.to_string(),
+            iat: row.issued_at as u64,
+            exp: row.expires_at as u64,
+        },
+    };
+
+    let query = "UPDATE sessions SET last_seen_at = now() WHERE id = $1";
+    let statement = sqlx::query(query).bind(hash_token(id));
+
+    if let Err(err) = database::mutate_statement(db, "sessions.update_last_seen", statement).await {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not refresh session : {}", err),
+        ));
+    }
+
+    Ok(session)
+}
+
+// pub async fn delete_session() removes the session, so it can no longer be used.
+pub async fn delete_session(db: &Database, id: &str) -> Result<(), SystemError> {
+    let statement = sqlx::query("DELETE FROM sessions WHERE id = $1").bind(hash_token(id));
+
+    if let Err(err) = database::mutate_statement(db, "sessions.delete", statement).await {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not delete session : {}", err),
+        ));
+    }
+
+    Ok(())
+}
+
+// pub fn session_cookies() returns the Set-Cookie values for a new session.
+pub fn session_cookies(config: &SessionConfig, session: &NewSession) -> Vec<String> {
+    let max_age = config.absolute_timeout.as_secs();
+
+    vec![
+        cookie(config, SESSION_COOKIE, &session.id, max_age, true),
+        cookie(config, CSRF_COOKIE, &session.csrf_token, max_age, false),
+    ]
+}
+
+// pub fn clear_cookies() returns the Set-Cookie values that remove our session cookies from the browser.
+pub fn clear_cookies(config: &SessionConfig) -> Vec<String> {
+    vec![
+        cookie(config, SESSION_COOKIE, "", 0, true),
+        cookie(config, CSRF_COOKIE, "", 0, false),
+    ]
+}
+
+// pub fn read_cookie() finds the value of a cookie sent with the request.
+pub fn read_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, val)| val.to_string())
+}
+
+// pub fn verify_csrf() performs the double submit check, the header must match both the cookie and the session.
+pub fn verify_csrf(headers: &HeaderMap, session: &Session) -> Result<(), SystemError> {
+    let header = headers.get(CSRF_HEADER).and_then(|val| val.to_str().ok());
+    let cookie = read_cookie(headers, CSRF_COOKIE);
+
+    let valid = match (header, cookie) {
+        (Some(header), Some(cookie)) => {
+            constant_time_eq(header, &cookie) && constant_time_eq(header, &session.csrf_token)
+        }
+        _ => false,
+    };
+
+    if !valid {
+        return Err(SystemError::new(
+            StatusCode::FORBIDDEN,
+            "missing or invalid csrf token",
+        ));
+    }
+
+    Ok(())
+}
+
+// pub fn random_token() creates a hex encoded token from a cryptographically secure random number generator.
+pub fn random_token(bytes: usize) -> Result<String, SystemError> {
+    let mut buf = vec![0u8; bytes];
+
+    if let Err(err) = openssl::rand::rand_bytes(&mut buf) {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not generate random token : {}", err),
+        ));
+    }
+
+    Ok(to_hex(&buf))
+}
+
+// pub fn hash_token() hashes a token before it is stored, so a leaked table cannot be used to hijack a session.
+pub fn hash_token(token: &str) -> String {
+    to_hex(&openssl::sha::sha256(token.as_bytes()))
+}
+
+// pub fn constant_time_eq() compares two secrets without leaking how many characters matched.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && openssl::memcmp::eq(a.as_bytes(), b.as_bytes())
+}
+
+fn cookie(
+    config: &SessionConfig,
+    name: &str,
+    value: &str,
+    max_age: u64,
+    http_only: bool,
+) -> String {
+    let mut cookie = format!(
+        "{}={}; Path=/; Max-Age={}; SameSite={}",
+        name, value, max_age, config.same_site
+    );
+
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+
+    if config.secure {
+        cookie.push_str("; Secure");
+    }
+
+    cookie
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn session(csrf_token: &str) -> Session {
+        Session {
+            user_id: 1,
+            csrf_token: csrf_token.to_string(),
+            claims: StandardClaims::default(),
+        }
+    }
+
+    fn headers(header: Option<&str>, cookie: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        if let Some(header) = header {
+            headers.insert(CSRF_HEADER, HeaderValue::from_str(header).unwrap());
+        }
+
+        if let Some(cookie) = cookie {
+            let cookie = format!("session_id=abc; {}={}", CSRF_COOKIE, cookie);
+            headers.insert(header::COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        }
+
+        headers
+    }
+
+    #[test]
+    fn verify_csrf_accepts_matching_tokens() {
+        let headers = headers(Some("token"), Some("token"));
+        assert!(verify_csrf(&headers, &session("token")).is_ok());
+    }
+
+    #[test]
+    fn verify_csrf_rejects_mismatched_or_missing_tokens() {
+        let cases = [
+            headers(None, Some("token")),
+            headers(Some("token"), None),
+            headers(Some("token"), Some("other")),
+            headers(Some("other"), Some("other")),
+            headers(Some("toke"), Some("toke")),
+        ];
+
+        for headers in cases {
+            let err = verify_csrf(&headers, &session("token")).unwrap_err();
+            assert_eq!(err.status_code, StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[test]
+    fn read_cookie_finds_the_named_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::COOKIE, HeaderValue::from_static("a=1; b=2"));
+        headers.append(header::COOKIE, HeaderValue::from_static("c=3"));
+
+        assert_eq!(read_cookie(&headers, "b").as_deref(), Some("2"));
+        assert_eq!(read_cookie(&headers, "c").as_deref(), Some("3"));
+        assert_eq!(read_cookie(&headers, "d"), None);
+    }
+
+    #[test]
+    fn constant_time_eq_compares_length_and_content() {
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+    }
+}
@@ -0,0 +1,265 @@
+use super::session::constant_time_eq;
+use crate::domain::system::error::error::SystemError;
+use axum::http::StatusCode;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Totp implements time based one time passwords (RFC 6238), compatible with the common authenticator apps.
+
+// The number of seconds each code is valid for.
+pub const PERIOD: u64 = 30;
+// The number of digits in each code.
+pub const DIGITS: u32 = 6;
+// The number of periods either side of now we accept, to allow for clock drift on the device.
+const SKEW: u64 = 1;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// pub fn new_secret() creates a new random 160 bit secret, base32 encoded as authenticator apps expect.
+pub fn new_secret() -> Result<String, SystemError> {
+    let mut secret = [0u8; 20];
+
+    if let Err(err) = openssl::rand::rand_bytes(&mut secret) {
+        return Err(SystemError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not generate totp secret : {}", err),
+        ));
+    }
+
+    Ok(base32_encode(&secret))
+}
+
+// pub fn provisioning_uri() creates the otpauth:// uri that is usually displayed to the user as a QR code.
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        DIGITS,
+        PERIOD
+    )
+}
+
+// pub fn verify() checks the code against the secret, and returns the time step it matched. The step should be stored
+// and passed back in as last_step, so the same code cannot be used twice.
+pub fn verify(
+    secret: &str,
+    code: &str,
+    last_step: Option<i64>,
+) -> Result<Option<u64>, SystemError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / PERIOD;
+
+    verify_at(secret, code, last_step, now)
+}
+
+// fn verify_at() checks the code against the time steps either side of now.
+fn verify_at(
+    secret: &str,
+    code: &str,
+    last_step: Option<i64>,
+    now: u64,
+) -> Result<Option<u64>, SystemError> {
+    let key = match base32_decode(secret) {
+        Some(key) => key,
+        None => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "stored totp secret is not valid base32",
+            ))
+        }
+    };
+
+    for step in now.saturating_sub(SKEW)..=now + SKEW {
+        if let Some(last_step) = last_step {
+            if step as i64 <= last_step {
+                continue;
+            }
+        }
+
+        let expected = match generate(&key, step) {
+            Ok(expected) => expected,
+            Err(err) => return Err(err),
+        };
+
+        if constant_time_eq(&expected, code) {
+            return Ok(Some(step));
+        }
+    }
+
+    Ok(None)
+}
+
+// fn generate() creates the code for a given time step (RFC 4226 section 5.3).
+fn generate(key: &[u8], step: u64) -> Result<String, SystemError> {
+    let digest = match hmac_sha1(key, &step.to_be_bytes()) {
+        Ok(digest) => digest,
+        Err(err) => {
+            return Err(SystemError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not generate totp code : {}", err),
+            ))
+        }
+    };
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    Ok(format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    ))
+}
+
+fn hmac_sha1(key: &[u8], data: &[u8]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+    signer.update(data)?;
+    signer.sign_to_vec()
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut output = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            output.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 0x1f) as usize] as char);
+            bits -= 5;
+        }
+    }
+
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    output
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut output = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for char in data.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|val| *val == char.to_ascii_uppercase())?;
+
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+
+        if bits >= 8 {
+            output.push(((buffer >> (bits - 8)) & 0xff) as u8);
+            bits -= 8;
+        }
+    }
+
+    Some(output)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA1 secret from the test vectors of RFC 6238 appendix B, "12345678901234567890".
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn generate_matches_rfc_6238_test_vectors() {
+        // The RFC gives 8 digit codes, our 6 digit codes are the last 6 digits of each.
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+
+        let key = base32_decode(SECRET).unwrap();
+
+        for (time, code) in vectors {
+            assert_eq!(generate(&key, time / PERIOD).unwrap(), code, "time {}", time);
+        }
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(base32_encode(b"12345678901234567890"), SECRET);
+        assert_eq!(
+            base32_decode(&SECRET.to_lowercase()).unwrap(),
+            b"12345678901234567890"
+        );
+        assert!(base32_decode("not base32!").is_none());
+    }
+
+    #[test]
+    fn verify_accepts_codes_within_the_skew() {
+        let key = base32_decode(SECRET).unwrap();
+        let now = 1234567890 / PERIOD;
+
+        for step in [now - 1, now, now + 1] {
+            let code = generate(&key, step).unwrap();
+            assert_eq!(verify_at(SECRET, &code, None, now).unwrap(), Some(step));
+        }
+
+        for step in [now - 2, now + 2] {
+            let code = generate(&key, step).unwrap();
+            assert_eq!(verify_at(SECRET, &code, None, now).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn verify_rejects_replayed_codes() {
+        let key = base32_decode(SECRET).unwrap();
+        let now = 1234567890 / PERIOD;
+        let code = generate(&key, now).unwrap();
+
+        // A code from a step that has already been used, or one before it, is rejected.
+        assert_eq!(verify_at(SECRET, &code, Some(now as i64), now).unwrap(), None);
+        assert_eq!(
+            verify_at(SECRET, &code, Some(now as i64 + 1), now).unwrap(),
+            None
+        );
+
+        // A later code is still accepted.
+        let next = generate(&key, now + 1).unwrap();
+        assert_eq!(
+            verify_at(SECRET, &next, Some(now as i64), now).unwrap(),
+            Some(now + 1)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_an_invalid_secret() {
+        assert!(verify_at("not base32!", "123456", None, 0).is_err());
+    }
+}
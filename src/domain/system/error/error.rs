@@ -1,12 +1,18 @@
 use axum::{
-    http::status::StatusCode,
+    http::{header, status::StatusCode, HeaderValue},
     response::{IntoResponse, Response},
 };
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct SystemError {
     pub status_code: StatusCode,
     pub message: String,
+    // The value of the WWW-Authenticate header, this is only set for 401 and 403 errors so the client
+    // knows which scheme to authenticate with, and why the credentials were rejected.
+    pub challenge: Option<String>,
+    // The value of the Retry-After header, this is only set for 429 errors so the client knows when to try again.
+    pub retry_after: Option<Duration>,
 }
 
 impl SystemError {
@@ -14,12 +20,43 @@ impl SystemError {
         Self {
             status_code,
             message: message.into(),
+            challenge: None,
+            retry_after: None,
         }
     }
     pub fn new_internal_server_error() -> Self {
         Self {
             status_code: StatusCode::INTERNAL_SERVER_ERROR,
             message: String::from("Internal Server Error"),
+            challenge: None,
+            retry_after: None,
+        }
+    }
+    // 401, the request is missing credentials, or the credentials provided are not valid.
+    pub fn new_unauthorised(challenge: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            status_code: StatusCode::UNAUTHORIZED,
+            message: message.into(),
+            challenge: Some(challenge.into()),
+            retry_after: None,
+        }
+    }
+    // 403, the credentials are valid, but do not grant access to the resource.
+    pub fn new_forbidden(challenge: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            status_code: StatusCode::FORBIDDEN,
+            message: message.into(),
+            challenge: Some(challenge.into()),
+            retry_after: None,
+        }
+    }
+    // 429, the client has made too many requests, and can try again once the retry after has passed.
+    pub fn new_too_many_requests(retry_after: Duration, message: impl Into<String>) -> Self {
+        Self {
+            status_code: StatusCode::TOO_MANY_REQUESTS,
+            message: message.into(),
+            challenge: None,
+            retry_after: Some(retry_after),
         }
     }
 }
@@ -27,6 +64,25 @@ impl SystemError {
 impl IntoResponse for SystemError {
     fn into_response(self) -> Response {
         // its often easiest to implement `IntoResponse` by calling other implementations
-        (self.status_code, self.message).into_response()
+        let mut response = (self.status_code, self.message).into_response();
+
+        // Attach our challenge so clients know how to (re)authenticate.
+        if let Some(challenge) = self.challenge {
+            if let Ok(value) = HeaderValue::from_str(&challenge) {
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, value);
+            }
+        }
+
+        // Retry-After is given in whole seconds, so we round up to never ask the client to retry too early.
+        if let Some(retry_after) = self.retry_after {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+
+        response
     }
 }
@@ -1,13 +1,15 @@
-use crate::{domain::system::error::error::SystemError, lib::database};
+use crate::{
+    domain::system::error::error::SystemError,
+    lib::{database, metrics::metrics},
+};
 use axum::http::header;
 use axum::response::IntoResponse;
 use axum::{extract::State, http::Request, middleware::Next};
-use sqlx::PgPool;
 
 // AuditContext contains all the state required to succefully audit a request.
 #[derive(Clone)]
 pub struct AuditContext {
-    pub db: PgPool,
+    pub db: database::database::Database,
 }
 
 // This one will use an extractor and post handler logic to add to audit logs.
@@ -61,8 +63,13 @@ pub async fn audit<B>(
         .bind(request_uuid.as_hyphenated().to_string())
         .bind(format!("{}", status_code.as_u16()));
 
+    // The audit log write is in flight until the statement completes.
+    let _in_flight = metrics::track(&metrics::global().audit_writes_in_flight);
+
     // Insert a new user record into the database using the mutate_statement()
-    if let Err(err) = database::database::mutate_statement(&context.db, statement).await {
+    if let Err(err) =
+        database::database::mutate_statement(&context.db, "audit_logs.insert", statement).await
+    {
         return Err(SystemError::new(
             axum::http::StatusCode::INTERNAL_SERVER_ERROR,
             err.to_string(),
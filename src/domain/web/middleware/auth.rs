@@ -1,68 +1,68 @@
-use crate::domain::{
-    system::{auth::auth::Auth, error::error::SystemError},
-    web::state::state::SharedState,
+use crate::domain::system::{
+    auth::{
+        auth::{bearer_challenge, Auth, StandardClaims, INVALID_REQUEST},
+        session::{read_cookie, SESSION_COOKIE},
+    },
+    error::error::SystemError,
+};
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, HeaderMap, Method, Request},
+    middleware::Next,
+    response::IntoResponse,
 };
-use axum::{extract::State, http::Request, middleware::Next, response::IntoResponse, Extension};
 
 // AuthContext contains all the state required to succefully auth a request.
 #[derive(Clone)]
 pub struct AuthContext {
     pub auth: Auth,
+    // Routes that can be reached without a token, these are matched against the route as it was
+    // registered on the router, for example /v1/users/:id.
+    pub public_routes: Vec<String>,
+}
+
+impl AuthContext {
+    // fn is_public() checks if the route being requested has been allow-listed as public.
+    fn is_public<B>(&self, request: &Request<B>) -> bool {
+        let path = match request.extensions().get::<MatchedPath>() {
+            Some(path) => path.as_str(),
+            None => request.uri().path(),
+        };
+
+        self.public_routes.iter().any(|route| route == path)
+    }
 }
 
 pub async fn authenticate<B>(
-    Extension(state): Extension<SharedState>,
     State(context): State<AuthContext>,
-    request: Request<B>,
+    mut request: Request<B>,
     next: Next<B>,
 ) -> Result<impl IntoResponse, SystemError> {
     // Pre Handler Logic
 
-    // Lock our shared state with write access to update claims to our state on succesful
-    // authentication.
-    let mut state = state.write().await;
+    // Requests without credentials continue with empty claims, so handlers can always extract them.
+    let mut claims = StandardClaims::default();
 
     if context.auth.enabled {
-        let token = match request.headers().get(axum::http::header::AUTHORIZATION) {
-            Some(token) => token,
-            None => {
-                return Err(SystemError::new(
-                    axum::http::StatusCode::FORBIDDEN,
-                    "no authorisation header provided",
-                ));
-            }
-        };
-
-        let token = match token.to_str().ok() {
-            Some(token) => token,
-            None => {
-                return Err(SystemError::new(
-                    axum::http::StatusCode::FORBIDDEN,
-                    "no authorisation header provided",
-                ));
-            }
+        // Public routes still attach claims when a token is sent, but do not require one.
+        let optional = context.is_public(&request);
+
+        claims = match validate_credentials(
+            &context.auth,
+            request.method(),
+            request.headers(),
+            optional,
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err(err) => return Err(err),
         };
-
-        let parts = token.split_whitespace().collect::<Vec<&str>>();
-
-        if parts.len() != 2 {
-            return Err(SystemError::new(
-                axum::http::StatusCode::FORBIDDEN,
-                "no valid authorisation header provided",
-            ));
-        }
-
-        if let Err(err) = context.auth.authenticate(parts[1].to_string(), &mut state) {
-            return Err(err);
-        }
     }
 
-    // Because we are calling the next handler, and RWLOCK requires read access for other functions
-    // down the stack, we need to drop the lock manually as the scope is not technically ended
-    // and the write lock has completed.
-    drop(state);
-
-    // Do something with claims, how to safely share between state?
+    // The claims belong to this request only, so they are stored in its extensions for handlers to read with
+    // Extension<StandardClaims>, and never in the state shared between requests.
+    request.extensions_mut().insert(claims);
 
     let response = next.run(request).await;
 
@@ -71,30 +71,156 @@ pub async fn authenticate<B>(
     Ok(response)
 }
 
+// RoleContext contains the roles allowed to reach the routes it is layered on.
+#[derive(Clone)]
+pub struct RoleContext {
+    pub auth: Auth,
+    pub roles: Vec<String>,
+}
+
+// authorise() is layered on the routes that need a role, with route_layer so it runs after authenticate().
 pub async fn authorise<B>(
-    roles: Option<Vec<String>>,
-    Extension(state): Extension<SharedState>,
-    State(context): State<AuthContext>,
+    State(context): State<RoleContext>,
     request: Request<B>,
     next: Next<B>,
 ) -> Result<impl IntoResponse, SystemError> {
     // Pre Handler Logic
 
-    let state = state.read().await;
-
     if context.auth.enabled {
-        if let Err(err) = context.auth.authorise(&state, roles) {
+        // authenticate() always runs first, so any request without claims has not been authenticated.
+        let claims = match request.extensions().get::<StandardClaims>() {
+            Some(claims) => claims,
+            None => {
+                return Err(SystemError::new_unauthorised(
+                    bearer_challenge(None, None),
+                    "an authenticated user is required",
+                ))
+            }
+        };
+
+        if let Err(err) = context.auth.authorise(claims, Some(context.roles.clone())) {
             return Err(err);
         }
     }
 
-    // Because we are calling the next handler, and RWLOCK requires read access for other functions
-    // down the stack, we need to drop the lock manually as the scope is not technically ended.
-    drop(state);
-
     let response = next.run(request).await;
 
     // Post Handler Logic
 
     Ok(response)
 }
+
+// fn validate_credentials() validates the credentials and returns their claims. A bearer token is always
+// preferred, and when sessions are enabled, the session cookie is used as a fallback. When optional, a request
+// without credentials is treated as anonymous, but credentials that are sent must still be valid.
+async fn validate_credentials(
+    auth: &Auth,
+    method: &Method,
+    headers: &HeaderMap,
+    optional: bool,
+) -> Result<StandardClaims, SystemError> {
+    if !headers.contains_key(header::AUTHORIZATION) && auth.session.enabled {
+        if let Some(id) = read_cookie(headers, SESSION_COOKIE) {
+            return match auth.authenticate_session(&id, method, headers).await {
+                Ok(claims) => Ok(claims),
+                // An expired or revoked session is treated as anonymous on public routes, otherwise the user
+                // could never reach the login route again to replace it.
+                Err(_) if optional => Ok(StandardClaims::default()),
+                Err(err) => Err(err),
+            };
+        }
+    }
+
+    if optional && !headers.contains_key(header::AUTHORIZATION) {
+        return Ok(StandardClaims::default());
+    }
+
+    let token = match bearer_token(headers) {
+        Ok(token) => token,
+        Err(err) => return Err(err),
+    };
+
+    auth.authenticate(token)
+}
+
+// fn bearer_token() extracts the token from the authorization header, the header must use the Bearer scheme.
+// Any missing or malformed credentials are a 401, as the client has not yet proven who they are.
+fn bearer_token(headers: &HeaderMap) -> Result<String, SystemError> {
+    let header = match headers.get(header::AUTHORIZATION) {
+        Some(header) => header,
+        None => {
+            return Err(SystemError::new_unauthorised(
+                bearer_challenge(None, None),
+                "no authorisation header provided",
+            ));
+        }
+    };
+
+    let header = match header.to_str() {
+        Ok(header) => header,
+        Err(_) => {
+            return Err(SystemError::new_unauthorised(
+                bearer_challenge(
+                    Some(INVALID_REQUEST),
+                    Some("malformed authorization header"),
+                ),
+                "authorisation header contains invalid characters",
+            ));
+        }
+    };
+
+    // The scheme is case insensitive, and is followed by exactly one token.
+    let parts = header.split_whitespace().collect::<Vec<&str>>();
+
+    if parts.len() != 2 || !parts[0].eq_ignore_ascii_case("bearer") {
+        return Err(SystemError::new_unauthorised(
+            bearer_challenge(Some(INVALID_REQUEST), Some("expected a bearer token")),
+            "no valid bearer authorisation header provided",
+        ));
+    }
+
+    Ok(parts[1].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderValue, StatusCode};
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(authorization).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn bearer_token_accepts_any_case_scheme() {
+        assert_eq!(bearer_token(&headers("Bearer abc")).unwrap(), "abc");
+        assert_eq!(bearer_token(&headers("bearer  abc")).unwrap(), "abc");
+        assert_eq!(bearer_token(&headers("BEARER abc")).unwrap(), "abc");
+    }
+
+    #[test]
+    fn bearer_token_without_a_header_has_no_error_code() {
+        let err = bearer_token(&HeaderMap::new()).unwrap_err();
+
+        assert_eq!(err.status_code, StatusCode::UNAUTHORIZED);
+        assert_eq!(err.challenge.as_deref(), Some("Bearer"));
+    }
+
+    #[test]
+    fn bearer_token_rejects_malformed_headers() {
+        for authorization in ["Basic abc", "Bearer", "Bearer abc def", "abc"] {
+            let err = bearer_token(&headers(authorization)).unwrap_err();
+
+            assert_eq!(err.status_code, StatusCode::UNAUTHORIZED);
+            assert!(err
+                .challenge
+                .unwrap()
+                .starts_with("Bearer error=\"invalid_request\""));
+        }
+    }
+}
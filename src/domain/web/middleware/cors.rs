@@ -0,0 +1,270 @@
+use crate::domain::system::error::error::SystemError;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Duration;
+use tokio::sync::watch;
+
+// The origin every origin is allowed by.
+const ANY_ORIGIN: &str = "*";
+
+// Which browser origins can call the API, and what they can send. CORS is disabled when no origins are allowed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CorsConfig {
+    // Origins like https://example.com, or * for any origin.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    // When true, browsers send cookies, and the origin is always echoed back rather than *.
+    pub allow_credentials: bool,
+    // How long browsers cache the answer to a preflight request.
+    pub max_age: Duration,
+}
+
+// CorsContext contains all the state required to answer CORS requests, the config is read from the channel, so the
+// allowed origins can change while the application is running.
+#[derive(Clone)]
+pub struct CorsContext {
+    pub config: watch::Receiver<CorsConfig>,
+}
+
+impl CorsConfig {
+    // fn validate() checks the settings are safe together. Allowing any origin with credentials would echo back
+    // every origin along with Access-Control-Allow-Credentials, letting any site make requests with the user's
+    // cookies.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allow_credentials && self.allowed_origins.iter().any(|o| o == ANY_ORIGIN) {
+            return Err(String::from(
+                "must not be true when any origin (*) is allowed, list each allowed origin instead",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // fn allows() checks if the origin is allowed.
+    fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == ANY_ORIGIN || allowed == origin)
+    }
+
+    // fn allow_origin() returns the value of Access-Control-Allow-Origin for an allowed origin.
+    fn allow_origin(&self, origin: &str) -> String {
+        match self.allow_credentials || !self.allowed_origins.iter().any(|o| o == ANY_ORIGIN) {
+            true => origin.to_string(),
+            false => String::from(ANY_ORIGIN),
+        }
+    }
+}
+
+pub async fn cors<B>(
+    State(context): State<CorsContext>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, SystemError> {
+    // Pre Handler Logic
+
+    // Requests without an origin are not from a browser, or are from the same origin.
+    let origin = match request
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+    {
+        Some(origin) => origin.to_string(),
+        None => return Ok(next.run(request).await),
+    };
+
+    let config = context.config.borrow().clone();
+    let allowed = config.allows(&origin);
+
+    // A preflight request is answered here, as the routes do not handle OPTIONS.
+    let preflight = request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+    if preflight {
+        if !allowed {
+            return Ok(StatusCode::FORBIDDEN.into_response());
+        }
+
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        allow(headers, &config, &origin);
+        insert(
+            headers,
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            &config.allowed_methods.join(", "),
+        );
+        insert(
+            headers,
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            &config.allowed_headers.join(", "),
+        );
+        insert(
+            headers,
+            header::ACCESS_CONTROL_MAX_AGE,
+            &config.max_age.as_secs().to_string(),
+        );
+
+        return Ok(response);
+    }
+
+    let mut response = next.run(request).await;
+
+    // Post Handler Logic
+
+    // Origins that are not allowed get no CORS headers, so the browser does not hand the response to the page.
+    if allowed {
+        allow(response.headers_mut(), &config, &origin);
+    }
+
+    Ok(response)
+}
+
+// fn allow() adds the headers that allow the origin to read the response.
+fn allow(headers: &mut HeaderMap, config: &CorsConfig, origin: &str) {
+    insert(
+        headers,
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        &config.allow_origin(origin),
+    );
+
+    if config.allow_credentials {
+        insert(headers, header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+    }
+
+    // The response differs by origin, so caches must not share it between origins.
+    headers.append(header::VARY, HeaderValue::from_static("origin"));
+}
+
+fn insert(headers: &mut HeaderMap, name: header::HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn config(origins: &[&str], allow_credentials: bool) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            allowed_methods: vec![String::from("GET"), String::from("POST")],
+            allowed_headers: vec![String::from("content-type"), String::from("x-csrf-token")],
+            allow_credentials,
+            max_age: Duration::from_secs(600),
+        }
+    }
+
+    async fn send(config: CorsConfig, request: Request<Body>) -> Response {
+        let (_sender, receiver) = watch::channel(config);
+
+        Router::new()
+            .route("/", get(|| async {}))
+            .layer(middleware::from_fn_with_state(
+                CorsContext { config: receiver },
+                cors,
+            ))
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    fn preflight(origin: &str) -> Request<Body> {
+        Request::options("/")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn validate_rejects_any_origin_with_credentials() {
+        assert!(config(&["*"], true).validate().is_err());
+        assert!(config(&["https://example.com", "*"], true)
+            .validate()
+            .is_err());
+
+        assert!(config(&["*"], false).validate().is_ok());
+        assert!(config(&["https://example.com"], true).validate().is_ok());
+        assert!(config(&[], true).validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn preflight_is_answered_for_allowed_origins() {
+        let response = send(
+            config(&["https://example.com"], true),
+            preflight("https://example.com"),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type, x-csrf-token"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+    }
+
+    #[tokio::test]
+    async fn preflight_is_forbidden_for_other_origins() {
+        let response = send(
+            config(&["https://example.com"], false),
+            preflight("https://evil.example"),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn any_origin_is_allowed_without_credentials() {
+        let request = Request::get("/")
+            .header(header::ORIGIN, "https://example.com")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = send(config(&["*"], false), request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+        assert_eq!(response.headers()[header::VARY], "origin");
+    }
+
+    #[tokio::test]
+    async fn requests_from_other_origins_get_no_cors_headers() {
+        let request = Request::get("/")
+            .header(header::ORIGIN, "https://evil.example")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = send(config(&["https://example.com"], true), request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
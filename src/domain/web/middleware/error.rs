@@ -1,6 +1,18 @@
-use axum::{extract::State, http::Request, middleware::Next, response::IntoResponse};
+use axum::{
+    extract::State,
+    http::{header, Request},
+    middleware::Next,
+    response::IntoResponse,
+};
 
-use crate::{domain::system::error::error::SystemError, lib::logger::logger::Logger};
+use crate::{
+    domain::system::{
+        auth::auth::{bearer_challenge, INSUFFICIENT_SCOPE},
+        error::error::SystemError,
+    },
+    lib::logger::logger::Logger,
+};
+use std::time::Duration;
 
 // ErrorContext contains all the state required to succefully handle request errors.
 #[derive(Clone)]
@@ -26,6 +38,22 @@ pub async fn error<B>(
 
             let status = response.status();
 
+            // We keep hold of any challenge before the body is consumed, so the rewritten 401 and 403
+            // responses still tell the client how to authenticate.
+            let challenge = response
+                .headers()
+                .get(header::WWW_AUTHENTICATE)
+                .and_then(|val| val.to_str().ok())
+                .map(String::from);
+
+            // As is the retry after of a 429, so the client still knows when to try again.
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|val| val.to_str().ok())
+                .and_then(|val| val.parse::<u64>().ok())
+                .map(Duration::from_secs);
+
             let data = match hyper::body::to_bytes(response.into_body()).await {
                 Ok(data) => data,
                 Err(err) => {
@@ -57,17 +85,28 @@ pub async fn error<B>(
 
             match status.as_u16() {
                 401 => {
-                    return Err(SystemError::new(
-                        status,
+                    return Err(SystemError::new_unauthorised(
+                        challenge.unwrap_or_else(|| bearer_challenge(None, None)),
                         "you are not authorised to access this resource",
                     ));
                 }
                 403 => {
-                    return Err(SystemError::new(
-                        status,
+                    return Err(SystemError::new_forbidden(
+                        challenge
+                            .unwrap_or_else(|| bearer_challenge(Some(INSUFFICIENT_SCOPE), None)),
                         "you are forbidden to access this resource",
                     ));
                 }
+                429 => {
+                    if let Some(retry_after) = retry_after {
+                        return Err(SystemError::new_too_many_requests(
+                            retry_after,
+                            data.to_string(),
+                        ));
+                    }
+
+                    return Err(SystemError::new(status, data.to_string()));
+                }
                 400..=499 => {
                     return Err(SystemError::new(status, data.to_string()));
                 }
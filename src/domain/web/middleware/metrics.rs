@@ -0,0 +1,35 @@
+use crate::{domain::system::error::error::SystemError, lib::metrics::metrics};
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::IntoResponse};
+use std::time::Instant;
+
+// The route label of requests that did not match a route, so unknown paths can not create new labels.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// This one records the count, and latency of every request, labelled by the route template (for example
+// /v1/users/:id) rather than the path, so the number of labels stays small.
+pub async fn metrics<B>(
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<impl IntoResponse, SystemError> {
+    // Pre Handler Logic
+
+    let method = request.method().to_string();
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(route) => route.as_str().to_string(),
+        None => String::from(UNMATCHED_ROUTE),
+    };
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    // Post Handler Logic
+
+    metrics::global().observe_request(
+        &method,
+        &route,
+        response.status().as_str(),
+        started.elapsed(),
+    );
+
+    Ok(response)
+}
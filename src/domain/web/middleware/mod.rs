@@ -1,4 +1,6 @@
 pub mod audit;
 pub mod auth;
+pub mod cors;
 pub mod error;
 pub mod logging;
+pub mod metrics;
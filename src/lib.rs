@@ -22,7 +22,15 @@ pub mod domain {
 
 // Your lib modules here.
 pub mod lib {
+    pub mod build;
+    pub mod conf;
     pub mod database;
+    pub mod features;
+    pub mod health;
+    pub mod limiter;
     pub mod logger;
+    pub mod mailer;
+    pub mod metrics;
+    pub mod secret;
     pub mod server;
 }
@@ -0,0 +1,47 @@
+use serde::Serialize;
+
+// Info describes the build of the running binary, it is embedded at compile time by build.rs.
+#[derive(Serialize, Clone, Debug)]
+pub struct Info {
+    // The version of the crate, with the short commit, for example 0.1.0+1a2b3c4.
+    pub version: String,
+    pub git_commit: &'static str,
+    // Whether the build had uncommitted changes, this is only accurate for clean builds, see build.rs.
+    pub git_dirty: bool,
+    // Seconds since the unix epoch.
+    pub build_timestamp: u64,
+    pub rustc_version: &'static str,
+    pub profile: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+// fn info() returns the build information of the running binary.
+pub fn info() -> Info {
+    Info {
+        version: version(),
+        git_commit: env!("BUILD_GIT_COMMIT"),
+        git_dirty: env!("BUILD_GIT_DIRTY") == "true",
+        build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or_default(),
+        rustc_version: env!("BUILD_RUSTC_VERSION"),
+        profile: env!("BUILD_PROFILE"),
+        features: env!("BUILD_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+    }
+}
+
+// fn version() returns the version of the crate, with the short commit as build metadata, for example 0.1.0+1a2b3c4.
+// A build with uncommitted changes is marked as dirty, for example 0.1.0+1a2b3c4.dirty.
+pub fn version() -> String {
+    let commit = env!("BUILD_GIT_COMMIT");
+    let short_commit = commit.get(..7).unwrap_or(commit);
+
+    let mut version = format!("{}+{}", env!("CARGO_PKG_VERSION"), short_commit);
+
+    if env!("BUILD_GIT_DIRTY") == "true" {
+        version.push_str(".dirty");
+    }
+
+    version
+}
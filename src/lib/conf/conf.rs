@@ -0,0 +1,937 @@
+use crate::lib::secret::secret::{self, Secret};
+use serde::{de::DeserializeOwned, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+// The file extensions of config files, in the order they are looked for.
+const EXTENSIONS: [&str; 3] = ["toml", "yaml", "yml"];
+// The name of the base config file, without its extension.
+const BASE_FILE: &str = "base";
+// Flags and env vars used by the loader itself, rather than a setting.
+const CONFIG_FLAG: &str = "CONFIG";
+const CONFIG_FILE_ENV: &str = "CONFIG_FILE";
+const ENVIRONMENT: &str = "ENVIRONMENT";
+
+// Settings are loaded in layers, each layer overriding the fields set by the layers before it:
+//
+//   compiled defaults < base file < environment file < env vars (and .env) < CLI flags
+//
+// The base file is config/base.toml (or .yaml/.yml), or the file given with --config or CONFIG_FILE. The environment
+// file sits next to it, named after the environment, for example config/production.toml. Each group of settings is
+// a table in the files named after its prefix, for example [db], with the app settings at the top level.
+// A setting DB_PORT is set with the env var DB_PORT, the flag --db-port 5432, or port under [db].
+pub struct Layers {
+    environment: String,
+    files: Vec<(PathBuf, Value)>,
+    env: BTreeMap<String, String>,
+    // The value of each flag, keyed by the env var name it sets, for example --db-port is DB_PORT.
+    flags: BTreeMap<String, Flag>,
+    // Where the effective value of each setting came from, filled in as settings are loaded.
+    sources: Mutex<BTreeMap<String, Source>>,
+    used_flags: Mutex<BTreeSet<String>>,
+    warnings: Vec<String>,
+}
+
+struct Flag {
+    name: String,
+    value: String,
+}
+
+// Configuration for the loader.
+#[derive(Clone)]
+pub struct Config {
+    // The command line arguments, without the program name.
+    pub args: Vec<String>,
+    // Where config/base.toml, and the environment files are looked for.
+    pub directory: PathBuf,
+    // Used to find the environment file, when the environment is not set by a flag, env var or the base file.
+    pub default_environment: String,
+}
+
+// Where the effective value of a setting came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Flag(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::Flag(name) => write!(f, "flag {}", name),
+        }
+    }
+}
+
+impl Serialize for Source {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// A setting that could not be loaded, naming the setting, and where its value came from.
+#[derive(Debug)]
+pub struct Error {
+    // The setting, for example db.port, or the file that could not be read.
+    pub key: String,
+    pub source: Source,
+    pub reason: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid config {} (from {}) : {}",
+            self.key, self.source, self.reason
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Every problem found while loading and validating the settings, so they can all be fixed at once rather than one
+// start up at a time.
+#[derive(Debug, Default)]
+pub struct Report {
+    problems: Vec<Error>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Report::default()
+    }
+
+    pub fn push(&mut self, problem: Error) {
+        self.problems.push(problem);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn problems(&self) -> &[Error] {
+        &self.problems
+    }
+}
+
+impl From<Error> for Report {
+    fn from(problem: Error) -> Self {
+        Report {
+            problems: vec![problem],
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "found {} config problem(s)", self.problems.len())?;
+
+        for problem in &self.problems {
+            write!(
+                f,
+                "\n  - {} (from {}) : {}",
+                problem.key, problem.source, problem.reason
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Report {}
+
+// The derive macro, #[derive(Conf)], see macros/conf_derive for its attributes.
+pub use conf_derive::Conf;
+
+// Conf is implemented by every group of settings, so it can be loaded from the layers. This is usually derived.
+pub trait Conf: Serialize + DeserializeOwned {
+    // The table in the config files, and the prefix of the env vars and flags. The app settings use no prefix.
+    const PREFIX: &'static str;
+
+    // fn defaults() returns the settings used when no layer sets them.
+    fn defaults() -> Self;
+
+    // fn env_names() returns the fields that are read from a different env var than PREFIX_FIELD.
+    fn env_names() -> &'static [(&'static str, &'static str)] {
+        &[]
+    }
+
+    // fn validate() returns every field that is not valid.
+    fn validate(&self) -> Vec<Invalid> {
+        vec![]
+    }
+
+    // fn load() overrides the defaults with any value from the layers, and validates the result.
+    fn load(layers: &Layers) -> Result<Self, Report> {
+        layers.load::<Self>()
+    }
+}
+
+// A field that failed validation.
+#[derive(Debug, Clone)]
+pub struct Invalid {
+    pub field: String,
+    pub reason: String,
+}
+
+impl Invalid {
+    pub fn new(field: &str, reason: impl Into<String>) -> Self {
+        Invalid {
+            field: field.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+// IsEmpty is used by validate(non_empty).
+pub trait IsEmpty {
+    fn is_empty(&self) -> bool;
+}
+
+impl IsEmpty for String {
+    fn is_empty(&self) -> bool {
+        String::is_empty(self)
+    }
+}
+
+impl<T> IsEmpty for Vec<T> {
+    fn is_empty(&self) -> bool {
+        Vec::is_empty(self)
+    }
+}
+
+impl<T: IsEmpty> IsEmpty for Secret<T> {
+    fn is_empty(&self) -> bool {
+        self.expose().is_empty()
+    }
+}
+
+// fn new() reads every layer of config, the .env file is loaded into the env vars first if there is one.
+pub fn new(config: Config) -> Result<Layers, Error> {
+    let mut warnings = vec![];
+
+    if let Err(err) = dotenvy::dotenv() {
+        warnings.push(format!("no .env file was loaded : {}", err));
+    }
+
+    let flags = match parse_flags(&config.args) {
+        Ok(flags) => flags,
+        Err(err) => return Err(err),
+    };
+
+    // Empty env vars are treated as unset, so a copied .env.example does not override everything with nothing.
+    let env: BTreeMap<String, String> =
+        env::vars().filter(|(_, value)| !value.is_empty()).collect();
+
+    // The base file is either given, or looked for in the config directory.
+    let base_file = match (flags.get(CONFIG_FLAG), env.get(CONFIG_FILE_ENV)) {
+        (Some(flag), _) => Some(PathBuf::from(&flag.value)),
+        (None, Some(path)) => Some(PathBuf::from(path)),
+        (None, None) => find_file(&config.directory, BASE_FILE),
+    };
+
+    let mut files = vec![];
+
+    if let Some(path) = &base_file {
+        match read_file(path) {
+            Ok(file) => files.push((path.clone(), file)),
+            Err(err) => return Err(err),
+        }
+    }
+
+    // The environment decides which environment file is read, so it can not come from that file.
+    let environment = match (flags.get(ENVIRONMENT), env.get(ENVIRONMENT)) {
+        (Some(flag), _) => flag.value.clone(),
+        (None, Some(environment)) => environment.clone(),
+        (None, None) => files
+            .first()
+            .and_then(|(_, file)| file.get("environment"))
+            .and_then(Value::as_str)
+            .map(String::from)
+            .unwrap_or(config.default_environment),
+    };
+
+    let directory = match &base_file {
+        Some(path) => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        None => config.directory,
+    };
+
+    if let Some(path) = find_file(&directory, &environment) {
+        match read_file(&path) {
+            Ok(file) => files.push((path, file)),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(Layers {
+        environment,
+        files,
+        env,
+        flags,
+        sources: Mutex::new(BTreeMap::new()),
+        used_flags: Mutex::new(BTreeSet::from([
+            String::from(CONFIG_FLAG),
+            String::from(ENVIRONMENT),
+        ])),
+        warnings,
+    })
+}
+
+impl Layers {
+    // fn load() returns the settings, or every problem with them when any setting is invalid.
+    pub fn load<T: Conf>(&self) -> Result<T, Report> {
+        let mut report = Report::new();
+
+        match self.check::<T>(&mut report) {
+            Some(settings) if report.is_empty() => Ok(settings),
+            _ => Err(report),
+        }
+    }
+
+    // fn check() loads the settings, adding every problem with them to the report. The settings are still returned
+    // when a field fails validation, so checks across groups of settings can run, and be reported together.
+    pub fn check<T: Conf>(&self, report: &mut Report) -> Option<T> {
+        let settings = match self.merge::<T>() {
+            Ok(settings) => settings,
+            Err(problems) => {
+                report.problems.extend(problems.problems);
+                return None;
+            }
+        };
+
+        for problem in self.validate(&settings) {
+            report.push(problem);
+        }
+
+        Some(settings)
+    }
+
+    // fn merge() merges each field of the defaults with the layers, and names every setting with a value that can not
+    // be read.
+    fn merge<T: Conf>(&self) -> Result<T, Report> {
+        let prefix = T::PREFIX;
+        let section = prefix.to_lowercase();
+        let defaults = T::defaults();
+
+        // The defaults are copied with their secrets, as they are only used to be merged.
+        let defaults = match secret::exposed(|| serde_json::to_value(&defaults)) {
+            Ok(Value::Object(defaults)) => defaults,
+            Ok(_) => {
+                return Err(Report::from(Error {
+                    key: section,
+                    source: Source::Default,
+                    reason: String::from("settings must be a struct"),
+                }))
+            }
+            Err(err) => {
+                return Err(Report::from(Error {
+                    key: section,
+                    source: Source::Default,
+                    reason: err.to_string(),
+                }))
+            }
+        };
+
+        let mut merged = defaults.clone();
+        let mut overridden = vec![];
+
+        for (name, default) in &defaults {
+            let key = match section.as_str() {
+                "" => name.clone(),
+                section => format!("{}.{}", section, name),
+            };
+            let env_name = match (
+                T::env_names().iter().find(|(field, _)| field == name),
+                prefix,
+            ) {
+                (Some((_, env_name)), _) => env_name.to_string(),
+                (None, "") => name.to_uppercase(),
+                (None, prefix) => format!("{}_{}", prefix.to_uppercase(), name.to_uppercase()),
+            };
+
+            let mut value = None;
+
+            for (path, file) in &self.files {
+                let table = match section.as_str() {
+                    "" => Some(file),
+                    section => file.get(section),
+                };
+
+                if let Some(file_value) = table.and_then(|table| table.get(name)) {
+                    value = Some((file_value.clone(), Source::File(path.clone())));
+                }
+            }
+
+            if let Some(env_value) = self.env.get(&env_name) {
+                value = Some((from_text(env_value, default), Source::Env(env_name.clone())));
+            }
+
+            if let Some(flag) = self.flags.get(&env_name) {
+                value = Some((
+                    from_text(&flag.value, default),
+                    Source::Flag(flag.name.clone()),
+                ));
+                self.used_flags().insert(env_name.clone());
+            }
+
+            let source = match value {
+                Some((value, source)) => {
+                    merged.insert(name.clone(), value);
+                    overridden.push((name.clone(), key.clone(), source.clone()));
+                    source
+                }
+                None => Source::Default,
+            };
+
+            self.loaded_sources().insert(key, source);
+        }
+
+        match serde_json::from_value::<T>(Value::Object(merged.clone())) {
+            Ok(settings) => return Ok(settings),
+            Err(err) if overridden.is_empty() => {
+                return Err(Report::from(Error {
+                    key: section,
+                    source: Source::Default,
+                    reason: err.to_string(),
+                }))
+            }
+            Err(_) => {}
+        }
+
+        // Serde does not name the field that failed, so each overridden value is tried on its own to find them.
+        let mut report = Report::new();
+
+        for (name, key, source) in overridden {
+            let mut single = defaults.clone();
+            single.insert(name.clone(), merged[&name].clone());
+
+            if let Err(err) = serde_json::from_value::<T>(Value::Object(single)) {
+                report.push(Error {
+                    key,
+                    source,
+                    reason: err.to_string(),
+                });
+            }
+        }
+
+        if report.is_empty() {
+            report.push(Error {
+                key: section,
+                source: Source::Default,
+                reason: String::from("the settings are not valid together"),
+            });
+        }
+
+        Err(report)
+    }
+
+    // fn validate() names every field of the settings that is not valid, and where its value came from.
+    fn validate<T: Conf>(&self, settings: &T) -> Vec<Error> {
+        let section = T::PREFIX.to_lowercase();
+
+        settings
+            .validate()
+            .into_iter()
+            .map(|invalid| {
+                let key = match section.as_str() {
+                    "" => invalid.field,
+                    section => format!("{}.{}", section, invalid.field),
+                };
+
+                self.problem(&key, invalid.reason)
+            })
+            .collect()
+    }
+
+    // fn problem() names a setting that is not valid, along with where its value came from. This is used for checks
+    // across settings, that can not be made by validating a single group.
+    pub fn problem(&self, key: &str, reason: impl Into<String>) -> Error {
+        let source = match self.loaded_sources().get(key) {
+            Some(source) => source.clone(),
+            None => Source::Default,
+        };
+
+        Error {
+            key: key.to_string(),
+            source,
+            reason: reason.into(),
+        }
+    }
+
+    // fn environment() returns the environment used to pick the environment file.
+    pub fn environment(&self) -> &str {
+        &self.environment
+    }
+
+    // fn files() returns the config files that were read, in the order they were applied.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files.iter().map(|(path, _)| path.clone()).collect()
+    }
+
+    // fn sources() returns where the effective value of every loaded setting came from.
+    pub fn sources(&self) -> BTreeMap<String, Source> {
+        self.loaded_sources().clone()
+    }
+
+    // fn flag() returns the value of a flag that is not a setting, for example flag("check-config").
+    // A flag given without a value is "true".
+    pub fn flag(&self, name: &str) -> Option<&str> {
+        let key = flag_key(name);
+        let value = self.flags.get(&key).map(|flag| flag.value.as_str());
+
+        if value.is_some() {
+            self.used_flags().insert(key);
+        }
+
+        value
+    }
+
+    // fn unused_flags() returns any flag that did not match a setting, or was read with flag(). This should be
+    // checked once every setting has been loaded, so a mistyped flag is not silently ignored.
+    pub fn unused_flags(&self) -> Vec<String> {
+        let used = self.used_flags();
+
+        self.flags
+            .iter()
+            .filter(|(key, _)| !used.contains(*key))
+            .map(|(_, flag)| flag.name.clone())
+            .collect()
+    }
+
+    // fn warnings() returns anything noteworthy from reading the layers, like a missing .env file.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    fn loaded_sources(&self) -> MutexGuard<'_, BTreeMap<String, Source>> {
+        match self.sources.lock() {
+            Ok(sources) => sources,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn used_flags(&self) -> MutexGuard<'_, BTreeSet<String>> {
+        match self.used_flags.lock() {
+            Ok(used) => used,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+// fn parse_flags() reads flags in the form --name value, --name=value, or --name on its own, which is true.
+fn parse_flags(args: &[String]) -> Result<BTreeMap<String, Flag>, Error> {
+    let mut flags = BTreeMap::new();
+    let mut args = args.iter().peekable();
+
+    while let Some(arg) = args.next() {
+        let name = match arg.strip_prefix("--") {
+            Some(name) if !name.is_empty() => name,
+            _ => {
+                return Err(Error {
+                    key: arg.clone(),
+                    source: Source::Flag(arg.clone()),
+                    reason: String::from("expected a flag, like --db-port 5432"),
+                })
+            }
+        };
+
+        let (name, value) = match name.split_once('=') {
+            Some((name, value)) => (name, value.to_string()),
+            None => match args.peek() {
+                Some(value) if !value.starts_with("--") => {
+                    (name, args.next().cloned().unwrap_or_default())
+                }
+                _ => (name, String::from("true")),
+            },
+        };
+
+        flags.insert(
+            flag_key(name),
+            Flag {
+                name: format!("--{}", name),
+                value,
+            },
+        );
+    }
+
+    Ok(flags)
+}
+
+// fn flag_key() returns the env var name a flag sets, for example db-port is DB_PORT.
+fn flag_key(name: &str) -> String {
+    name.replace('-', "_").to_uppercase()
+}
+
+// fn from_text() converts the text of an env var or flag to the type of the default, so it can be deserialised.
+// Text that does not convert is kept as a string, so deserialising reports it as the wrong type.
+fn from_text(text: &str, default: &Value) -> Value {
+    match default {
+        Value::Bool(_) => match text.to_lowercase().as_str() {
+            "true" | "1" | "yes" => Value::Bool(true),
+            "false" | "0" | "no" => Value::Bool(false),
+            _ => Value::String(text.to_string()),
+        },
+        Value::Number(_) => match serde_json::from_str::<serde_json::Number>(text.trim()) {
+            Ok(number) => Value::Number(number),
+            Err(_) => Value::String(text.to_string()),
+        },
+        // Lists are comma separated, for example AUTH_PUBLIC_ROUTES=/v1/users,/v1/users/:id
+        Value::Array(values) => Value::Array(
+            text.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| from_text(item, values.first().unwrap_or(&Value::Null)))
+                .collect(),
+        ),
+        _ => Value::String(text.to_string()),
+    }
+}
+
+// fn find_file() returns the first config file with the name, and a known extension in the directory.
+fn find_file(directory: &Path, name: &str) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| directory.join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file())
+}
+
+// fn read_file() reads a TOML or YAML config file, decided by its extension.
+fn read_file(path: &Path) -> Result<Value, Error> {
+    let invalid = |reason: String| Error {
+        key: path.display().to_string(),
+        source: Source::File(path.to_path_buf()),
+        reason,
+    };
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => return Err(invalid(err.to_string())),
+    };
+
+    let file = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str::<Value>(&text).map_err(|err| err.to_string()),
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str::<Value>(&text).map_err(|err| err.to_string())
+        }
+        _ => Err(String::from("config files must be .toml, .yaml or .yml")),
+    };
+
+    match file {
+        Ok(Value::Object(file)) => Ok(Value::Object(file)),
+        Ok(Value::Null) => Ok(Value::Object(Map::new())),
+        Ok(_) => Err(invalid(String::from(
+            "the file must contain a table of settings",
+        ))),
+        Err(err) => Err(invalid(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct TestConf {
+        host: String,
+        port: u16,
+        debug: bool,
+        origins: Vec<String>,
+    }
+
+    impl Conf for TestConf {
+        const PREFIX: &'static str = "TEST";
+
+        fn defaults() -> Self {
+            TestConf {
+                host: String::from("default"),
+                port: 1,
+                debug: false,
+                origins: vec![String::from("default")],
+            }
+        }
+
+        fn validate(&self) -> Vec<Invalid> {
+            match self.port {
+                0 => vec![Invalid::new("port", "must not be 0")],
+                _ => vec![],
+            }
+        }
+    }
+
+    // The derived fixture names this crate with crate = "crate", as the derive names it as an external crate.
+    #[derive(Conf, Debug, Serialize, Deserialize)]
+    #[conf(prefix = "DERIVED", crate = "crate")]
+    struct DerivedConf {
+        #[conf(default = "localhost", validate(non_empty))]
+        host: String,
+        #[conf(default = 8080, validate(range(min = 1, max = 65535)))]
+        port: u32,
+        #[conf(default = "info", env = "LOG_LEVEL", validate(one_of("debug", "info")))]
+        level: String,
+        #[conf(validate(custom = "even"))]
+        workers: u8,
+        password: Secret<String>,
+    }
+
+    fn even(value: &u8) -> Result<(), String> {
+        match value % 2 {
+            0 => Ok(()),
+            _ => Err(String::from("must be even")),
+        }
+    }
+
+    fn layers(files: Vec<(&str, Value)>, env: &[(&str, &str)], args: &[&str]) -> Layers {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        Layers {
+            environment: String::from("test"),
+            files: files
+                .into_iter()
+                .map(|(path, file)| (PathBuf::from(path), file))
+                .collect(),
+            env: env
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            flags: parse_flags(&args).unwrap(),
+            sources: Mutex::new(BTreeMap::new()),
+            used_flags: Mutex::new(BTreeSet::new()),
+            warnings: vec![],
+        }
+    }
+
+    #[test]
+    fn each_layer_overrides_the_layers_before_it() {
+        let layers = layers(
+            vec![
+                (
+                    "base.toml",
+                    json!({"test": {"host": "base", "port": 2, "debug": true}}),
+                ),
+                (
+                    "test.toml",
+                    json!({"test": {"host": "environment", "port": 3}}),
+                ),
+            ],
+            &[("TEST_HOST", "env")],
+            &["--test-host", "flag"],
+        );
+
+        let settings = layers.load::<TestConf>().unwrap();
+
+        assert_eq!(settings.host, "flag");
+        assert_eq!(settings.port, 3);
+        assert!(settings.debug);
+        assert_eq!(settings.origins, vec!["default"]);
+
+        let sources = layers.sources();
+        assert_eq!(
+            sources["test.host"],
+            Source::Flag(String::from("--test-host"))
+        );
+        assert_eq!(
+            sources["test.port"],
+            Source::File(PathBuf::from("test.toml"))
+        );
+        assert_eq!(
+            sources["test.debug"],
+            Source::File(PathBuf::from("base.toml"))
+        );
+        assert_eq!(sources["test.origins"], Source::Default);
+    }
+
+    #[test]
+    fn env_vars_override_files_without_a_flag() {
+        let layers = layers(
+            vec![("base.toml", json!({"test": {"host": "base"}}))],
+            &[("TEST_HOST", "env")],
+            &[],
+        );
+
+        let settings = layers.load::<TestConf>().unwrap();
+
+        assert_eq!(settings.host, "env");
+        assert_eq!(
+            layers.sources()["test.host"],
+            Source::Env(String::from("TEST_HOST"))
+        );
+    }
+
+    #[test]
+    fn from_text_converts_to_the_type_of_the_default() {
+        for (text, expected) in [("true", true), ("1", true), ("YES", true), ("no", false)] {
+            assert_eq!(from_text(text, &json!(false)), json!(expected));
+        }
+        assert_eq!(from_text("maybe", &json!(false)), json!("maybe"));
+
+        assert_eq!(from_text(" 5432 ", &json!(1)), json!(5432));
+        assert_eq!(from_text("0.5", &json!(1.0)), json!(0.5));
+        assert_eq!(from_text("port", &json!(1)), json!("port"));
+
+        assert_eq!(
+            from_text("/a, /b,,", &json!(["/default"])),
+            json!(["/a", "/b"])
+        );
+        assert_eq!(from_text("1,2", &json!([0])), json!([1, 2]));
+        assert_eq!(from_text("", &json!(["/default"])), json!([]));
+
+        assert_eq!(from_text("5432", &json!("text")), json!("5432"));
+    }
+
+    #[test]
+    fn parse_flags_reads_every_form() {
+        let args: Vec<String> = [
+            "--db-port",
+            "5432",
+            "--host=a=b",
+            "--check-config",
+            "--debug",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+
+        let flags = parse_flags(&args).unwrap();
+
+        assert_eq!(flags["DB_PORT"].value, "5432");
+        assert_eq!(flags["DB_PORT"].name, "--db-port");
+        assert_eq!(flags["HOST"].value, "a=b");
+        assert_eq!(flags["CHECK_CONFIG"].value, "true");
+        assert_eq!(flags["DEBUG"].value, "true");
+    }
+
+    #[test]
+    fn parse_flags_rejects_anything_else() {
+        for arg in ["5432", "-p", "--"] {
+            let err = parse_flags(&[arg.to_string()]).err().unwrap();
+
+            assert_eq!(err.key, arg);
+        }
+    }
+
+    #[test]
+    fn unused_flags_are_reported() {
+        let layers = layers(
+            vec![],
+            &[],
+            &["--test-port", "2", "--check-config", "--tset-host", "x"],
+        );
+
+        layers.load::<TestConf>().unwrap();
+        assert_eq!(layers.flag("check-config"), Some("true"));
+
+        assert_eq!(layers.unused_flags(), vec![String::from("--tset-host")]);
+    }
+
+    #[test]
+    fn merge_names_the_invalid_key_and_its_source() {
+        let layers = layers(
+            vec![("base.toml", json!({"test": {"host": "base"}}))],
+            &[("TEST_PORT", "port")],
+            &[],
+        );
+
+        let report = layers.load::<TestConf>().unwrap_err();
+
+        assert_eq!(report.problems().len(), 1);
+        assert_eq!(report.problems()[0].key, "test.port");
+        assert_eq!(
+            report.problems()[0].source,
+            Source::Env(String::from("TEST_PORT"))
+        );
+        assert!(report.to_string().contains("test.port"));
+    }
+
+    #[test]
+    fn validate_names_the_invalid_key() {
+        let layers = layers(vec![], &[], &["--test-port", "0"]);
+
+        let report = layers.load::<TestConf>().unwrap_err();
+
+        assert_eq!(report.problems()[0].key, "test.port");
+        assert_eq!(report.problems()[0].reason, "must not be 0");
+        assert_eq!(
+            report.problems()[0].source,
+            Source::Flag(String::from("--test-port"))
+        );
+    }
+
+    #[test]
+    fn derive_uses_the_defaults() {
+        let defaults = DerivedConf::defaults();
+
+        assert_eq!(DerivedConf::PREFIX, "DERIVED");
+        assert_eq!(defaults.host, "localhost");
+        assert_eq!(defaults.port, 8080);
+        assert_eq!(defaults.level, "info");
+        assert_eq!(defaults.workers, 0);
+        assert_eq!(defaults.password.expose(), "");
+        assert!(defaults.validate().is_empty());
+    }
+
+    #[test]
+    fn derive_reads_the_env_names() {
+        let layers = layers(
+            vec![],
+            &[
+                ("DERIVED_HOST", "env"),
+                ("DERIVED_LEVEL", "ignored"),
+                ("LOG_LEVEL", "debug"),
+            ],
+            &[],
+        );
+
+        let settings = layers.load::<DerivedConf>().unwrap();
+
+        assert_eq!(settings.host, "env");
+        assert_eq!(settings.level, "debug");
+        assert_eq!(
+            layers.sources()["derived.level"],
+            Source::Env(String::from("LOG_LEVEL"))
+        );
+    }
+
+    #[test]
+    fn derive_validates_every_rule() {
+        let layers = layers(
+            vec![],
+            &[
+                ("DERIVED_HOST", " "),
+                ("DERIVED_PORT", "0"),
+                ("LOG_LEVEL", "trace"),
+                ("DERIVED_WORKERS", "3"),
+            ],
+            &["--derived-host="],
+        );
+
+        let report = layers.load::<DerivedConf>().unwrap_err();
+        let problems: Vec<(&str, &str)> = report
+            .problems()
+            .iter()
+            .map(|problem| (problem.key.as_str(), problem.reason.as_str()))
+            .collect();
+
+        assert_eq!(
+            problems,
+            vec![
+                ("derived.host", "must not be empty"),
+                ("derived.port", "must be at least 1"),
+                ("derived.level", "must be one of debug, info"),
+                ("derived.workers", "must be even"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use super::conf::{self, Layers, Report};
+use crate::lib::logger::logger::Logger;
+use serde::Serialize;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    time::interval,
+};
+
+// The origin of the logs written while reloading.
+const ORIGIN: &str = "Config Reload";
+
+// Configuration for reloading a subset of the settings while the application is running.
+pub struct Config<T> {
+    // How the layers are read, the same as on start up.
+    pub layers: conf::Config,
+    // How often the config files are checked for changes.
+    pub interval: Duration,
+    // Loads, and validates the settings that can be reloaded from the layers.
+    pub load: fn(&Layers) -> Result<T, Report>,
+    pub logger: Logger,
+}
+
+// Reloader re-reads the settings on SIGHUP, or when a config file changes, and publishes them through a watch
+// channel. When the new settings are not valid, the current ones are kept, and the problems are logged.
+pub struct Reloader<T> {
+    config: Arc<Config<T>>,
+    settings: Arc<watch::Sender<Arc<T>>>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl<T> Clone for Reloader<T> {
+    fn clone(&self) -> Self {
+        Reloader {
+            config: self.config.clone(),
+            settings: self.settings.clone(),
+            status: self.status.clone(),
+        }
+    }
+}
+
+// The outcome of the reloads so far, times are in seconds since the unix epoch.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Status {
+    // The number of times new settings were published, 0 until the first change.
+    pub generation: u64,
+    // What started the last reload, for example SIGHUP.
+    pub last_trigger: Option<String>,
+    pub last_attempt_at: Option<u64>,
+    pub last_success_at: Option<u64>,
+    // Why the last reload failed, cleared once a reload succeeds.
+    pub last_error: Option<String>,
+    // The config files that were read, and are watched for changes.
+    pub files: Vec<PathBuf>,
+}
+
+// fn new() creates a reloader, publishing the settings loaded on start up until they are first reloaded.
+pub fn new<T>(config: Config<T>, initial: T, files: Vec<PathBuf>) -> Reloader<T> {
+    let (settings, _) = watch::channel(Arc::new(initial));
+
+    Reloader {
+        config: Arc::new(config),
+        settings: Arc::new(settings),
+        status: Arc::new(Mutex::new(Status {
+            files,
+            ..Status::default()
+        })),
+    }
+}
+
+impl<T: PartialEq + Send + Sync + 'static> Reloader<T> {
+    // fn subscribe() returns a receiver of the settings, which sees every reload.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<T>> {
+        self.settings.subscribe()
+    }
+
+    // fn current() returns the settings that are currently published.
+    pub fn current(&self) -> Arc<T> {
+        self.settings.borrow().clone()
+    }
+
+    pub fn status(&self) -> Status {
+        self.lock_status().clone()
+    }
+
+    // fn reload() reads the layers again, and publishes the settings when they changed. The status is returned either
+    // way, with the reason the reload failed when it did.
+    pub fn reload(&self, trigger: &str) -> Status {
+        // Held while loading, so reloads started together are applied one after the other.
+        let mut status = self.lock_status();
+        let now = unix_now();
+
+        let loaded = match conf::new(self.config.layers.clone()) {
+            Ok(layers) => match (self.config.load)(&layers) {
+                Ok(settings) => Ok((settings, layers.files())),
+                Err(report) => Err(report.to_string()),
+            },
+            Err(err) => Err(err.to_string()),
+        };
+
+        status.last_trigger = Some(trigger.to_string());
+        status.last_attempt_at = Some(now);
+
+        match loaded {
+            Ok((settings, files)) => {
+                status.last_success_at = Some(now);
+                status.last_error = None;
+                status.files = files;
+
+                let changed = self.settings.send_if_modified(|current| {
+                    if **current == settings {
+                        return false;
+                    }
+                    *current = Arc::new(settings);
+                    true
+                });
+
+                if changed {
+                    status.generation += 1;
+                    self.config.logger.info_w(
+                        format!(
+                            "config reloaded after {}, generation {}",
+                            trigger, status.generation
+                        )
+                        .as_str(),
+                        Some(ORIGIN),
+                    );
+                } else {
+                    self.config.logger.info_w(
+                        format!("config reloaded after {}, nothing changed", trigger).as_str(),
+                        Some(ORIGIN),
+                    );
+                }
+            }
+            Err(err) => {
+                self.config.logger.error_w(
+                    format!(
+                        "could not reload config after {}, keeping the current settings : {}",
+                        trigger, err
+                    )
+                    .as_str(),
+                    Some(ORIGIN),
+                );
+                status.last_error = Some(err);
+            }
+        }
+
+        status.clone()
+    }
+
+    // async fn watch() reloads the settings on SIGHUP, or whenever a config file changes, until shutdown.
+    pub async fn watch(self, mut shutdown: watch::Receiver<bool>) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(err) => {
+                self.config.logger.warn_w(
+                    format!(
+                        "could not listen for SIGHUP, only file changes reload : {}",
+                        err
+                    )
+                    .as_str(),
+                    Some(ORIGIN),
+                );
+                None
+            }
+        };
+
+        let mut modified = self.modified();
+        let mut ticker = interval(self.config.interval);
+
+        loop {
+            let trigger = tokio::select! {
+                _ = ticker.tick() => {
+                    let latest = self.modified();
+                    if latest == modified {
+                        continue;
+                    }
+                    "a config file changed"
+                },
+                Some(_) = async {
+                    match hangup.as_mut() {
+                        Some(hangup) => hangup.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => "SIGHUP",
+                _ = shutdown.changed() => {
+                    if *shutdown.borrow() {
+                        return;
+                    }
+                    continue;
+                },
+            };
+
+            // Reading the files, and validating the settings blocks, so it is kept off the runtime the same as a
+            // reload from the debug server.
+            let reloader = self.clone();
+
+            match tokio::task::spawn_blocking(move || {
+                reloader.reload(trigger);
+
+                // The files may have changed, so the ones read by this reload are watched from now on.
+                reloader.modified()
+            })
+            .await
+            {
+                Ok(latest) => modified = latest,
+                Err(err) => self.config.logger.error_w(
+                    format!("could not reload config after {} : {}", trigger, err).as_str(),
+                    Some(ORIGIN),
+                ),
+            }
+        }
+    }
+
+    // fn modified() returns when each watched file was last modified, a file that can not be read has no time.
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.lock_status()
+            .files
+            .iter()
+            .map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+            .collect()
+    }
+
+    fn lock_status(&self) -> MutexGuard<'_, Status> {
+        match self.status.lock() {
+            Ok(status) => status,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{conf::conf::Conf, logger::logger};
+    use serde::Deserialize;
+    use std::process;
+
+    #[derive(Conf, Debug, PartialEq, Serialize, Deserialize)]
+    #[conf(prefix = "RELOADTEST", crate = "crate")]
+    struct TestConf {
+        #[conf(default = 1, validate(range(min = 1)))]
+        limit: u32,
+    }
+
+    // fn reloader() returns a reloader of the settings in a new directory, with the base file containing the limit.
+    fn reloader(name: &str, limit: u32) -> (Reloader<TestConf>, PathBuf) {
+        let directory = std::env::temp_dir().join(format!("reload-{}-{}", process::id(), name));
+        fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("base.toml");
+        write_limit(&file, limit);
+
+        let config = Config {
+            layers: conf::Config {
+                args: vec![],
+                directory,
+                default_environment: String::from("test"),
+            },
+            interval: Duration::from_millis(10),
+            load: |layers| layers.load::<TestConf>(),
+            logger: logger::test_logger(),
+        };
+
+        let reloader = new(config, TestConf { limit }, vec![file.clone()]);
+
+        (reloader, file)
+    }
+
+    fn write_limit(file: &PathBuf, limit: u32) {
+        fs::write(file, format!("[reloadtest]\nlimit = {}\n", limit)).unwrap();
+    }
+
+    #[test]
+    fn reload_publishes_only_changed_settings() {
+        let (reloader, file) = reloader("changed", 5);
+        let receiver = reloader.subscribe();
+
+        let status = reloader.reload("a test");
+        assert_eq!(status.generation, 0);
+        assert_eq!(status.last_trigger.as_deref(), Some("a test"));
+        assert!(!receiver.has_changed().unwrap());
+
+        write_limit(&file, 6);
+
+        let status = reloader.reload("a test");
+        assert_eq!(status.generation, 1);
+        assert!(status.last_error.is_none());
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(reloader.current().limit, 6);
+    }
+
+    #[test]
+    fn reload_keeps_the_settings_when_invalid() {
+        let (reloader, file) = reloader("invalid", 5);
+
+        write_limit(&file, 0);
+
+        let status = reloader.reload("a test");
+        assert_eq!(status.generation, 0);
+        assert!(status.last_error.unwrap().contains("reloadtest.limit"));
+        assert_eq!(reloader.current().limit, 5);
+
+        write_limit(&file, 7);
+
+        let status = reloader.reload("a test");
+        assert!(status.last_error.is_none());
+        assert_eq!(reloader.current().limit, 7);
+    }
+
+    #[tokio::test]
+    async fn watch_reloads_when_a_file_changes() {
+        let (reloader, file) = reloader("watch", 5);
+        let mut receiver = reloader.subscribe();
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+
+        let watching = tokio::spawn(reloader.clone().watch(shutdown_receiver));
+
+        // The first tick records nothing new, so the file is changed after the watcher has started.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        write_limit(&file, 8);
+
+        tokio::time::timeout(Duration::from_secs(5), receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reloader.current().limit, 8);
+        assert_eq!(
+            reloader.status().last_trigger.as_deref(),
+            Some("a config file changed")
+        );
+
+        shutdown.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), watching)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
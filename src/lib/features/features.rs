@@ -0,0 +1,26 @@
+use std::collections::BTreeSet;
+use tokio::sync::watch;
+
+// Features holds the names of the feature flags that are turned on, so new behaviour can be switched on and off
+// without a deploy. The flags are read from the channel, so they can change while the application is running.
+#[derive(Clone)]
+pub struct Features {
+    enabled: watch::Receiver<BTreeSet<String>>,
+}
+
+// fn new() creates the features, turned on by the latest names sent through the channel.
+pub fn new(enabled: watch::Receiver<BTreeSet<String>>) -> Features {
+    Features { enabled }
+}
+
+impl Features {
+    // fn is_enabled() returns whether the feature flag is turned on, unknown flags are off.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.borrow().contains(name)
+    }
+
+    // fn enabled() returns every feature flag that is turned on.
+    pub fn enabled(&self) -> Vec<String> {
+        self.enabled.borrow().iter().cloned().collect()
+    }
+}
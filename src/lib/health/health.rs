@@ -0,0 +1,97 @@
+use serde::Serialize;
+use std::{
+    error::Error,
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::time::{sleep, timeout};
+
+// Configuration for how a health check is retried. Between attempts we wait with exponential backoff and jitter,
+// so many instances do not retry at the same moment.
+#[derive(Clone)]
+pub struct Config {
+    pub max_attempts: u32,
+    // How long a single attempt can take before it is treated as a failure.
+    pub timeout: Duration,
+    // The wait after the first failed attempt, this doubles after every attempt up to max_backoff.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_attempts: 3,
+            timeout: Duration::from_secs(2),
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+// The result of a health check.
+#[derive(Serialize, Clone, Debug)]
+pub struct Status {
+    pub name: String,
+    pub healthy: bool,
+    // How long the last attempt took.
+    pub latency_ms: u128,
+    pub attempts: u32,
+    // The error from the last attempt, when the check failed.
+    pub error: Option<String>,
+}
+
+// async fn check() runs the check until it succeeds, or the attempts run out. Each attempt is limited by the timeout.
+pub async fn check<F, Fut>(name: &str, config: &Config, mut check: F) -> Status
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), Box<dyn Error + Send + Sync>>>,
+{
+    let mut backoff = config.backoff;
+    let mut status = Status {
+        name: name.to_string(),
+        healthy: false,
+        latency_ms: 0,
+        attempts: 0,
+        error: None,
+    };
+
+    for attempt in 1..=config.max_attempts.max(1) {
+        let started = Instant::now();
+
+        let result = match timeout(config.timeout, check()).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {}ms", config.timeout.as_millis()).into()),
+        };
+
+        status.latency_ms = started.elapsed().as_millis();
+        status.attempts = attempt;
+
+        match result {
+            Ok(_) => {
+                status.healthy = true;
+                status.error = None;
+                return status;
+            }
+            Err(err) => status.error = Some(err.to_string()),
+        }
+
+        if attempt < config.max_attempts {
+            sleep(jitter(backoff)).await;
+            backoff = (backoff * 2).min(config.max_backoff);
+        }
+    }
+
+    status
+}
+
+// fn jitter() returns a random duration between half and all of the backoff.
+fn jitter(backoff: Duration) -> Duration {
+    let mut random = [0u8; 4];
+    if openssl::rand::rand_bytes(&mut random).is_err() {
+        return backoff;
+    }
+
+    let fraction = u32::from_le_bytes(random) as f64 / u32::MAX as f64;
+    backoff.mul_f64(0.5 + fraction / 2.0)
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+// A simple in memory rate limiter, that allows a number of attempts per key within a fixed window.
+// As this is in memory, each instance of the service keeps its own count.
+#[derive(Clone)]
+pub struct Limiter {
+    config: watch::Receiver<Config>,
+    attempts: Arc<Mutex<HashMap<String, (Instant, u32)>>>,
+}
+
+// Configuration to set the number of attempts allowed per window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub max_attempts: u32,
+    pub window: Duration,
+}
+
+// fn new() creates a new limiter, with limits that never change.
+pub fn new(config: Config) -> Limiter {
+    let (_, config) = watch::channel(config);
+
+    watched(config)
+}
+
+// fn watched() creates a new limiter, that uses the latest limits sent through the channel. Counts already
+// recorded are kept when the limits change.
+pub fn watched(config: watch::Receiver<Config>) -> Limiter {
+    Limiter {
+        config,
+        attempts: Arc::new(Mutex::new(HashMap::new())),
+    }
+}
+
+impl Limiter {
+    // fn check() records an attempt for the key. When the limit has been reached, the time until the
+    // window resets is returned as an error.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let config = self.config.borrow().clone();
+
+        let mut attempts = match self.attempts.lock() {
+            Ok(attempts) => attempts,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // Remove any windows that have ended, so the map does not grow forever.
+        attempts.retain(|_, (started, _)| now.duration_since(*started) < config.window);
+
+        let (started, count) = attempts.entry(key.to_string()).or_insert((now, 0));
+
+        if *count >= config.max_attempts {
+            return Err(config.window - now.duration_since(*started));
+        }
+
+        *count += 1;
+
+        Ok(())
+    }
+
+    // fn exhausted() checks if the limit has been reached for the key, without recording an attempt.
+    pub fn exhausted(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let config = self.config.borrow().clone();
+
+        let attempts = match self.attempts.lock() {
+            Ok(attempts) => attempts,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        match attempts.get(key) {
+            Some((started, count)) => {
+                now.duration_since(*started) < config.window && *count >= config.max_attempts
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    fn limiter(max_attempts: u32, window: Duration) -> Limiter {
+        new(Config {
+            max_attempts,
+            window,
+        })
+    }
+
+    #[test]
+    fn check_allows_attempts_up_to_the_limit() {
+        let limiter = limiter(2, Duration::from_secs(60));
+
+        assert!(limiter.check("a").is_ok());
+        assert!(!limiter.exhausted("a"));
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.exhausted("a"));
+
+        let retry_after = limiter.check("a").unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn check_counts_each_key_on_its_own() {
+        let limiter = limiter(1, Duration::from_secs(60));
+
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.check("a").is_err());
+        assert!(limiter.check("b").is_ok());
+        assert!(!limiter.exhausted("c"));
+    }
+
+    #[test]
+    fn check_resets_once_the_window_has_ended() {
+        let limiter = limiter(1, Duration::from_millis(50));
+
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.check("a").is_err());
+
+        sleep(Duration::from_millis(60));
+
+        assert!(!limiter.exhausted("a"));
+        assert!(limiter.check("a").is_ok());
+    }
+
+    #[test]
+    fn watched_limits_apply_to_the_next_check() {
+        let (send, recv) = watch::channel(Config {
+            max_attempts: 1,
+            window: Duration::from_secs(60),
+        });
+        let limiter = watched(recv);
+
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.check("a").is_err());
+
+        send.send_modify(|config| config.max_attempts = 2);
+
+        assert!(limiter.check("a").is_ok());
+    }
+}
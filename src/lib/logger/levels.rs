@@ -0,0 +1,457 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::task::JoinHandle;
+
+// Levels are the log levels that can be changed while the application is running. The global level applies to every
+// log, unless a module has its own level. A module is matched by the target of the log, which is the module path for
+// libraries (for example sqlx::query), and the logger name for our own Logger (for example external-api).
+#[derive(Clone)]
+pub struct Levels {
+    // The levels in use, the baseline with every active override applied on top.
+    filter: Arc<RwLock<Filter>>,
+    overrides: Arc<Mutex<Overrides>>,
+}
+
+#[derive(Clone)]
+struct Filter {
+    global: LevelFilter,
+    modules: BTreeMap<String, LevelFilter>,
+}
+
+struct Overrides {
+    // The levels from the config, and any change without a revert_after. Overrides are applied on top of the baseline
+    // as it is when they expire, so a reload of the config while an override is active is not lost.
+    baseline: Filter,
+    // Increased by every override, so a timer only removes its own override.
+    next_id: u64,
+    active: Vec<Override>,
+}
+
+// A change with a revert_after, which is removed by its timer.
+struct Override {
+    id: u64,
+    module: Option<String>,
+    level: Option<LevelFilter>,
+    // Seconds since the unix epoch when the override is removed.
+    at: u64,
+    handle: JoinHandle<()>,
+}
+
+// The current levels, as returned by GET /debug/log-level.
+#[derive(Serialize)]
+pub struct Snapshot {
+    pub global: String,
+    pub modules: BTreeMap<String, String>,
+    // Seconds since the unix epoch when the last override is reverted, and the levels are back to the baseline.
+    pub revert_at: Option<u64>,
+}
+
+// A change to the levels. Without a module the global level is changed, without a level the module uses the global
+// level again. With a revert_after the change is an override, which is removed once it has passed, leaving the
+// baseline and any other override.
+pub struct Change {
+    pub module: Option<String>,
+    pub level: Option<String>,
+    pub revert_after: Option<Duration>,
+}
+
+// fn new() creates the levels, with every log at the global level.
+pub fn new(global: LevelFilter) -> Levels {
+    log::set_max_level(global);
+
+    Levels {
+        filter: Arc::new(RwLock::new(Filter {
+            global,
+            modules: BTreeMap::new(),
+        })),
+        overrides: Arc::new(Mutex::new(Overrides {
+            baseline: Filter {
+                global,
+                modules: BTreeMap::new(),
+            },
+            next_id: 0,
+            active: vec![],
+        })),
+    }
+}
+
+impl Levels {
+    // fn apply_directives() applies directives in the format of RUST_LOG, for example "info,sqlx=warn", to the
+    // baseline. A directive without a module sets the global level. Nothing is applied if any directive is invalid.
+    // Active overrides stay in place until they expire.
+    pub fn apply_directives(&self, directives: &str) -> Result<(), String> {
+        let mut overrides = self.lock_overrides();
+        let mut baseline = overrides.baseline.clone();
+
+        for directive in directives
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+        {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let level = match parse_level(level) {
+                        Ok(level) => level,
+                        Err(err) => return Err(err),
+                    };
+                    baseline.modules.insert(module.trim().to_string(), level);
+                }
+                None => {
+                    baseline.global = match parse_level(directive) {
+                        Ok(level) => level,
+                        Err(err) => return Err(err),
+                    };
+                }
+            }
+        }
+
+        overrides.baseline = baseline;
+        self.set(overrides.effective());
+
+        Ok(())
+    }
+
+    // fn enabled() returns whether a log at the level, from the target is written.
+    pub fn enabled(&self, level: log::Level, target: &str) -> bool {
+        let filter = match self.filter.read() {
+            Ok(filter) => filter,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        level <= filter.level_for(target)
+    }
+
+    // fn snapshot() returns the current levels.
+    pub fn snapshot(&self) -> Snapshot {
+        let filter = self.current();
+
+        let revert_at = self
+            .lock_overrides()
+            .active
+            .iter()
+            .map(|active| active.at)
+            .max();
+
+        Snapshot {
+            global: filter.global.to_string(),
+            modules: filter
+                .modules
+                .iter()
+                .map(|(module, level)| (module.clone(), level.to_string()))
+                .collect(),
+            revert_at,
+        }
+    }
+
+    // fn change() applies the change. A change without a revert_after changes the baseline, and replaces any override
+    // of the same module, or of the global level. A change with a revert_after is an override, applied on top of the
+    // baseline until it expires. This must be called from within a tokio runtime when the change has a revert_after.
+    pub fn change(&self, change: Change) -> Result<Snapshot, String> {
+        let level = match change.level.as_deref().map(parse_level) {
+            Some(Ok(level)) => Some(level),
+            Some(Err(err)) => return Err(err),
+            None => None,
+        };
+
+        if change.module.is_none() && level.is_none() {
+            return Err(String::from(
+                "a level is required to change the global level",
+            ));
+        }
+
+        let mut overrides = self.lock_overrides();
+
+        match change.revert_after {
+            None => {
+                apply(&mut overrides.baseline, &change.module, level);
+
+                for replaced in overrides
+                    .active
+                    .iter()
+                    .filter(|active| active.module == change.module)
+                {
+                    replaced.handle.abort();
+                }
+                overrides
+                    .active
+                    .retain(|active| active.module != change.module);
+            }
+            Some(revert_after) => {
+                overrides.next_id += 1;
+                let id = overrides.next_id;
+
+                let at = SystemTime::now()
+                    .checked_add(revert_after)
+                    .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                    .map(|at| at.as_secs())
+                    .unwrap_or_default();
+
+                let levels = self.clone();
+                let handle = tokio::spawn(async move {
+                    tokio::time::sleep(revert_after).await;
+
+                    let mut overrides = levels.lock_overrides();
+                    overrides.active.retain(|active| active.id != id);
+                    levels.set(overrides.effective());
+                });
+
+                overrides.active.push(Override {
+                    id,
+                    module: change.module,
+                    level,
+                    at,
+                    handle,
+                });
+            }
+        }
+
+        self.set(overrides.effective());
+
+        drop(overrides);
+
+        Ok(self.snapshot())
+    }
+
+    fn current(&self) -> Filter {
+        match self.filter.read() {
+            Ok(filter) => filter.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn lock_overrides(&self) -> MutexGuard<'_, Overrides> {
+        match self.overrides.lock() {
+            Ok(overrides) => overrides,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn set(&self, filter: Filter) {
+        // The log macros skip anything above the max level before reaching us, so it is raised to the most verbose
+        // level in use.
+        log::set_max_level(filter.max_level());
+
+        match self.filter.write() {
+            Ok(mut current) => *current = filter,
+            Err(poisoned) => *poisoned.into_inner() = filter,
+        }
+    }
+}
+
+impl Overrides {
+    // fn effective() returns the baseline with every active override applied, the newest last.
+    fn effective(&self) -> Filter {
+        let mut filter = self.baseline.clone();
+
+        for active in &self.active {
+            apply(&mut filter, &active.module, active.level);
+        }
+
+        filter
+    }
+}
+
+// fn apply() sets the level of the module, or the global level. A module without a level uses the global level.
+fn apply(filter: &mut Filter, module: &Option<String>, level: Option<LevelFilter>) {
+    match (module, level) {
+        (Some(module), Some(level)) => {
+            filter.modules.insert(module.clone(), level);
+        }
+        (Some(module), None) => {
+            filter.modules.remove(module);
+        }
+        (None, Some(level)) => filter.global = level,
+        (None, None) => {}
+    }
+}
+
+impl Filter {
+    // fn level_for() returns the level of the most specific module matching the target, or the global level.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target == module.as_str()
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.global)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules.values().copied().fold(self.global, Ord::max)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    match LevelFilter::from_str(level.trim()) {
+        Ok(level) => Ok(level),
+        Err(_) => Err(format!(
+            "invalid log level {}, expected one of off, error, warn, info, debug, trace",
+            level
+        )),
+    }
+}
+
+// Filtered writes the logs allowed by the levels to the inner logger.
+pub struct Filtered<L: Log> {
+    pub levels: Levels,
+    pub inner: L,
+}
+
+impl<L: Log> Log for Filtered<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.levels.enabled(metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn change(module: Option<&str>, level: Option<&str>, revert_after: Option<u64>) -> Change {
+        Change {
+            module: module.map(String::from),
+            level: level.map(String::from),
+            revert_after: revert_after.map(Duration::from_secs),
+        }
+    }
+
+    // fn wait() lets the paused clock run past the duration, so any timer before it has fired.
+    async fn wait(seconds: u64) {
+        tokio::time::sleep(Duration::from_secs(seconds)).await;
+        tokio::task::yield_now().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn override_applies_until_it_expires() {
+        let levels = new(LevelFilter::Info);
+
+        let snapshot = levels
+            .change(change(Some("sqlx"), Some("trace"), Some(60)))
+            .unwrap();
+        assert_eq!(snapshot.modules["sqlx"], "TRACE");
+        assert!(snapshot.revert_at.is_some());
+        assert!(levels.enabled(Level::Trace, "sqlx::query"));
+        assert!(!levels.enabled(Level::Debug, "external-api"));
+
+        wait(61).await;
+
+        let snapshot = levels.snapshot();
+        assert!(snapshot.modules.is_empty());
+        assert!(snapshot.revert_at.is_none());
+        assert!(!levels.enabled(Level::Trace, "sqlx::query"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expiry_restores_the_current_baseline() {
+        let levels = new(LevelFilter::Info);
+
+        levels
+            .change(change(None, Some("trace"), Some(60)))
+            .unwrap();
+
+        // The config is reloaded while the override is active, the override still wins until it expires.
+        levels.apply_directives("warn,sqlx=error").unwrap();
+        assert_eq!(levels.snapshot().global, "TRACE");
+        assert_eq!(levels.snapshot().modules["sqlx"], "ERROR");
+
+        wait(61).await;
+
+        let snapshot = levels.snapshot();
+        assert_eq!(snapshot.global, "WARN");
+        assert_eq!(snapshot.modules["sqlx"], "ERROR");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overlapping_overrides_expire_on_their_own() {
+        let levels = new(LevelFilter::Info);
+
+        levels
+            .change(change(None, Some("debug"), Some(60)))
+            .unwrap();
+        wait(30).await;
+        levels
+            .change(change(Some("sqlx"), Some("trace"), Some(60)))
+            .unwrap();
+
+        // The first override expires, leaving the second.
+        wait(31).await;
+        let snapshot = levels.snapshot();
+        assert_eq!(snapshot.global, "INFO");
+        assert_eq!(snapshot.modules["sqlx"], "TRACE");
+        assert!(snapshot.revert_at.is_some());
+
+        wait(30).await;
+        let snapshot = levels.snapshot();
+        assert_eq!(snapshot.global, "INFO");
+        assert!(snapshot.modules.is_empty());
+        assert!(snapshot.revert_at.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overlapping_overrides_of_the_same_module_apply_the_newest() {
+        let levels = new(LevelFilter::Info);
+
+        levels
+            .change(change(Some("sqlx"), Some("debug"), Some(120)))
+            .unwrap();
+        levels
+            .change(change(Some("sqlx"), Some("trace"), Some(60)))
+            .unwrap();
+        assert_eq!(levels.snapshot().modules["sqlx"], "TRACE");
+
+        wait(61).await;
+        assert_eq!(levels.snapshot().modules["sqlx"], "DEBUG");
+
+        wait(60).await;
+        assert!(levels.snapshot().modules.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn change_without_a_timer_replaces_the_override() {
+        let levels = new(LevelFilter::Info);
+
+        levels
+            .change(change(Some("sqlx"), Some("trace"), Some(60)))
+            .unwrap();
+        levels
+            .change(change(Some("sqlx"), Some("warn"), None))
+            .unwrap();
+
+        wait(61).await;
+
+        let snapshot = levels.snapshot();
+        assert_eq!(snapshot.modules["sqlx"], "WARN");
+        assert!(snapshot.revert_at.is_none());
+    }
+
+    #[test]
+    fn invalid_changes_are_rejected() {
+        let levels = new(LevelFilter::Info);
+
+        assert!(levels.change(change(None, None, None)).is_err());
+        assert!(levels.change(change(None, Some("loud"), None)).is_err());
+        assert!(levels.apply_directives("info,sqlx=loud").is_err());
+        assert_eq!(levels.snapshot().global, "INFO");
+    }
+}
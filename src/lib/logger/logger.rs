@@ -1,24 +1,36 @@
+use super::levels::{self, Filtered, Levels};
 use env_logger::{self, fmt};
 use log;
+use std::env;
 
 // A simple custom JSON logger, that is used across the project.
+// Logs are written with the name of the logger as their target, so its level can be changed on its own.
 #[derive(Clone)]
 pub struct Logger {
     name: String,
+    version: String,
+    levels: Levels,
 }
 
 // Configuration to set the name and max logging level for a given logger.
 pub struct Config {
     pub name: String,
+    // Added to every log, so it is known which build wrote it, see lib::build::build::version().
+    pub version: String,
     pub max_log_level: log::LevelFilter,
 }
 
 impl Logger {
+    // fn levels() returns the log levels, which can be changed while the application is running.
+    pub fn levels(&self) -> Levels {
+        self.levels.clone()
+    }
     // Custom INFO log that formats to JSON.
     pub fn info_w(&self, message: &str, origin: Option<&str>) {
         let level = "INFO";
-        let output = to_json(message, origin, level).to_string();
+        let output = to_json(message, origin, level, &self.version).to_string();
         log::info!(
+            target: self.name.as_str(),
             "\x1b[32m[{} {} (Log Below)]\x1b[32m\n\x1b[32m{}\x1b[32m",
             self.name,
             level,
@@ -28,8 +40,9 @@ impl Logger {
     // Custom WARNING log that formats to JSON.
     pub fn warn_w(&self, message: &str, origin: Option<&str>) {
         let level = "WARNING";
-        let output = to_json(message, origin, level).to_string();
+        let output = to_json(message, origin, level, &self.version).to_string();
         log::warn!(
+            target: self.name.as_str(),
             "\x1b[33m[{} {} (Log Below)]\x1b[33m\n\x1b[33m{}\x1b[33m",
             self.name,
             level,
@@ -39,8 +52,9 @@ impl Logger {
     // Custom ERROR log that formats to JSON.
     pub fn error_w(&self, error_message: &str, origin: Option<&str>) {
         let level = "ERROR";
-        let output = to_json(error_message, origin, level).to_string();
+        let output = to_json(error_message, origin, level, &self.version).to_string();
         log::error!(
+            target: self.name.as_str(),
             "\x1b[91;1m[{} {} (Log Below)]\x1b[91;1m\n\x1b[91;1m{}\x1b[91;1m",
             self.name,
             level,
@@ -50,8 +64,9 @@ impl Logger {
     // Custom DEBUG log that formats to JSON.
     pub fn debug_w(&self, message: &str, origin: Option<&str>) {
         let level = "DEBUG";
-        let output = to_json(message, origin, level).to_string();
+        let output = to_json(message, origin, level, &self.version).to_string();
         log::debug!(
+            target: self.name.as_str(),
             "\x1b[34m[{} {} (Log Below)]\x1b[34m\n\x1b[34m{}\x1b[34m",
             self.name,
             level,
@@ -61,23 +76,64 @@ impl Logger {
 }
 
 // fn new_logger() creates a new logger that sets logging to standard output.
+// The max log level is the global level, and can be overridden per module with RUST_LOG, for example
+// RUST_LOG=info,sqlx=warn. The levels can then be changed while running, see Logger::levels().
 pub fn new_logger(config: Config) -> Logger {
     // Sets the desired log levels we would like to log out to the standard output.
-    log::set_max_level(config.max_log_level);
+    let levels = levels::new(config.max_log_level);
+    let invalid = levels
+        .apply_directives(&env::var("RUST_LOG").unwrap_or_default())
+        .err();
 
-    // Allows logging to support standard outputs.
-    env_logger::Builder::from_default_env()
+    // Allows logging to support standard outputs, the levels decide what is written so everything is passed through.
+    let inner = env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Trace)
         .format_indent(None)
         .format_target(false)
         .format_timestamp(Some(fmt::TimestampPrecision::Seconds))
-        .init();
+        .build();
+
+    log::set_boxed_logger(Box::new(Filtered {
+        levels: levels.clone(),
+        inner,
+    }))
+    .expect("the logger can only be created once");
 
     // Create a Logger struct that contains functions for this specific logger.
-    Logger { name: config.name }
+    let logger = Logger {
+        name: config.name,
+        version: config.version,
+        levels,
+    };
+
+    if let Some(err) = invalid {
+        logger.warn_w(
+            format!("ignoring RUST_LOG : {}", err).as_str(),
+            Some("Logger"),
+        );
+    }
+
+    logger
+}
+
+// fn test_logger() returns a logger for unit tests, which is not set as the global logger, so it can be created by
+// every test.
+#[cfg(test)]
+pub fn test_logger() -> Logger {
+    Logger {
+        name: String::from("test"),
+        version: String::from("test"),
+        levels: levels::new(log::LevelFilter::Off),
+    }
 }
 
 // fn to_json() passes in the logging arguments, and formats into more readable, and a log that can be serialised.
-fn to_json(log_message: &str, origin: Option<&str>, level: &str) -> serde_json::value::Value {
+fn to_json(
+    log_message: &str,
+    origin: Option<&str>,
+    level: &str,
+    version: &str,
+) -> serde_json::value::Value {
     let origin = match origin {
         Some(origin) => origin,
         None => "No Origin Specified.",
@@ -87,6 +143,7 @@ fn to_json(log_message: &str, origin: Option<&str>, level: &str) -> serde_json::
 
     serde_json::json!({
         message_key: log_message,
-        "origin": Some(origin)
+        "origin": Some(origin),
+        "version": version
     })
 }
@@ -0,0 +1,201 @@
+use crate::lib::logger::logger::Logger;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::{
+    error::Error,
+    fs::{create_dir_all, write},
+    mem,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::task::JoinHandle;
+
+// A simple outbound mailer, that sends plain text emails over SMTP. For local development and tests, the
+// emails can instead be written to a directory, or printed to the console.
+#[derive(Clone)]
+pub struct Mailer {
+    from: String,
+    transport: Transport,
+    // Emails sent in the background, so they can be flushed on shutdown.
+    background: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    // Where failures to send in the background are logged, the default mailer has none as it only prints.
+    logger: Option<Logger>,
+}
+
+// The supported ways to deliver an email.
+#[derive(Clone)]
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(PathBuf),
+    Console,
+}
+
+// Configuration to create a new mailer, transport can be one of smtp, file or console.
+pub struct Config {
+    pub transport: String,
+    pub from: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: String,
+    pub smtp_password: String,
+    // The directory emails are written to when using the file transport.
+    pub directory: String,
+    pub logger: Logger,
+}
+
+// A single email to be sent.
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// fn new() creates a new mailer based on the transport provided.
+pub fn new(config: Config) -> Result<Mailer, Box<dyn Error>> {
+    let transport = match config.transport.as_str() {
+        "smtp" => {
+            let mut builder =
+                match AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host) {
+                    Ok(builder) => builder.port(config.smtp_port),
+                    Err(err) => return Err(Box::new(err)),
+                };
+
+            if !config.smtp_username.is_empty() {
+                builder = builder
+                    .credentials(Credentials::new(config.smtp_username, config.smtp_password));
+            }
+
+            Transport::Smtp(builder.build())
+        }
+        "file" => Transport::File(PathBuf::from(config.directory)),
+        "console" => Transport::Console,
+        transport => return Err(format!("unknown mail transport : {}", transport).into()),
+    };
+
+    Ok(Mailer {
+        from: config.from,
+        transport,
+        background: Arc::new(Mutex::new(vec![])),
+        logger: Some(config.logger),
+    })
+}
+
+impl Default for Mailer {
+    // The console mailer is used by default, so nothing is ever sent unless configured to.
+    fn default() -> Self {
+        Mailer {
+            from: String::from("no-reply@localhost"),
+            transport: Transport::Console,
+            background: Arc::new(Mutex::new(vec![])),
+            logger: None,
+        }
+    }
+}
+
+impl Mailer {
+    // async fn send() delivers the email using the configured transport.
+    pub async fn send(&self, mail: Mail) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let message = match self.build(&mail) {
+            Ok(message) => message,
+            Err(err) => return Err(err),
+        };
+
+        match &self.transport {
+            Transport::Smtp(smtp) => {
+                if let Err(err) = smtp.send(message).await {
+                    return Err(Box::new(err));
+                }
+            }
+            Transport::File(directory) => {
+                if let Err(err) = create_dir_all(directory) {
+                    return Err(Box::new(err));
+                }
+
+                // We name each file by when it was sent, so they are listed in order.
+                let sent_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos();
+
+                let path = directory.join(format!("{}.eml", sent_at));
+                if let Err(err) = write(path, message.formatted()) {
+                    return Err(Box::new(err));
+                }
+            }
+            Transport::Console => {
+                println!(
+                    "{}",
+                    String::from_utf8_lossy(message.formatted().as_slice())
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    // fn send_in_background() sends the email without waiting for it to be delivered, failures are only logged.
+    pub fn send_in_background(&self, mail: Mail) {
+        let mailer = self.clone();
+
+        let mut background = match self.background.lock() {
+            Ok(background) => background,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // Finished sends are removed first, so the set only holds what is still in flight.
+        background.retain(|handle| !handle.is_finished());
+
+        background.push(tokio::spawn(async move {
+            if let Err(err) = mailer.send(mail).await {
+                let message = format!("could not send email : {}", err);
+
+                match &mailer.logger {
+                    Some(logger) => logger.error_w(&message, Some("Mailer")),
+                    None => log::error!("{}", message),
+                }
+            }
+        }));
+    }
+
+    // async fn flush() waits for every email sent in the background to finish.
+    pub async fn flush(&self) {
+        let background = {
+            let mut background = match self.background.lock() {
+                Ok(background) => background,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            mem::take(&mut *background)
+        };
+
+        for handle in background {
+            handle.await.ok();
+        }
+    }
+
+    fn build(&self, mail: &Mail) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        let from = match self.from.parse::<Mailbox>() {
+            Ok(from) => from,
+            Err(err) => return Err(Box::new(err)),
+        };
+
+        let to = match mail.to.parse::<Mailbox>() {
+            Ok(to) => to,
+            Err(err) => return Err(Box::new(err)),
+        };
+
+        match Message::builder()
+            .from(from)
+            .to(to)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())
+        {
+            Ok(message) => Ok(message),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use std::{sync::OnceLock, time::Duration};
+
+// The content type of the Prometheus text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// Metrics contains every metric of this process, they are rendered in the Prometheus text format.
+pub struct Metrics {
+    registry: Registry,
+    // Requests handled, labelled by method, route and status.
+    pub http_requests: IntCounterVec,
+    // How long requests took in seconds, labelled by method, route and status.
+    pub http_request_duration: HistogramVec,
+    // The connections currently open in the database pool.
+    pub db_pool_size: IntGauge,
+    // The open connections that are not in use.
+    pub db_pool_idle: IntGauge,
+    // The tasks waiting for a connection from the pool.
+    pub db_pool_waiters: IntGauge,
+    // How long statements took in seconds, labelled by the query name and outcome.
+    pub db_query_duration: HistogramVec,
+    // 1 while reads are sent to the replica, 0 when they fall back to the primary, or there is no replica.
+    pub db_replica_healthy: IntGauge,
+    // The audit logs being written, each request writes its own, so there is no queue.
+    pub audit_writes_in_flight: IntGauge,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+// fn global() returns the metrics of this process, which are created on first use.
+pub fn global() -> &'static Metrics {
+    METRICS.get_or_init(new)
+}
+
+fn new() -> Metrics {
+    let registry = Registry::new();
+
+    // The names and labels below are fixed, so creating and registering them can only fail if they are changed
+    // to be invalid, or registered twice.
+    let http_requests = IntCounterVec::new(
+        Opts::new(
+            "http_requests_total",
+            "The number of HTTP requests handled.",
+        ),
+        &["method", "route", "status"],
+    )
+    .expect("valid http_requests_total metric");
+
+    let http_request_duration = HistogramVec::new(
+        HistogramOpts::new(
+            "http_request_duration_seconds",
+            "How long HTTP requests took to handle in seconds.",
+        ),
+        &["method", "route", "status"],
+    )
+    .expect("valid http_request_duration_seconds metric");
+
+    let db_pool_size = IntGauge::new(
+        "db_pool_connections",
+        "The number of connections open in the database pool.",
+    )
+    .expect("valid db_pool_connections metric");
+
+    let db_pool_idle = IntGauge::new(
+        "db_pool_idle_connections",
+        "The number of open connections in the database pool that are not in use.",
+    )
+    .expect("valid db_pool_idle_connections metric");
+
+    let db_pool_waiters = IntGauge::new(
+        "db_pool_waiters",
+        "The number of tasks waiting for a connection from the database pool.",
+    )
+    .expect("valid db_pool_waiters metric");
+
+    let db_query_duration = HistogramVec::new(
+        HistogramOpts::new(
+            "db_query_duration_seconds",
+            "How long database statements took to run in seconds.",
+        ),
+        &["query", "outcome"],
+    )
+    .expect("valid db_query_duration_seconds metric");
+
+    let db_replica_healthy = IntGauge::new(
+        "db_replica_healthy",
+        "1 while reads are sent to the database replica, 0 when they fall back to the primary.",
+    )
+    .expect("valid db_replica_healthy metric");
+
+    let audit_writes_in_flight = IntGauge::new(
+        "audit_writes_in_flight",
+        "The number of audit log writes in progress.",
+    )
+    .expect("valid audit_writes_in_flight metric");
+
+    registry
+        .register(Box::new(http_requests.clone()))
+        .expect("unique http_requests_total metric");
+    registry
+        .register(Box::new(http_request_duration.clone()))
+        .expect("unique http_request_duration_seconds metric");
+    registry
+        .register(Box::new(db_pool_size.clone()))
+        .expect("unique db_pool_connections metric");
+    registry
+        .register(Box::new(db_pool_idle.clone()))
+        .expect("unique db_pool_idle_connections metric");
+    registry
+        .register(Box::new(db_pool_waiters.clone()))
+        .expect("unique db_pool_waiters metric");
+    registry
+        .register(Box::new(db_query_duration.clone()))
+        .expect("unique db_query_duration_seconds metric");
+    registry
+        .register(Box::new(db_replica_healthy.clone()))
+        .expect("unique db_replica_healthy metric");
+    registry
+        .register(Box::new(audit_writes_in_flight.clone()))
+        .expect("unique audit_writes_in_flight metric");
+
+    // Process metrics (cpu, memory, open files, start time) are read from /proc, so only exist on linux.
+    #[cfg(target_os = "linux")]
+    registry
+        .register(Box::new(
+            prometheus::process_collector::ProcessCollector::for_self(),
+        ))
+        .expect("unique process metrics");
+
+    Metrics {
+        registry,
+        http_requests,
+        http_request_duration,
+        db_pool_size,
+        db_pool_idle,
+        db_pool_waiters,
+        db_query_duration,
+        db_replica_healthy,
+        audit_writes_in_flight,
+    }
+}
+
+impl Metrics {
+    // fn observe_request() records a handled request.
+    pub fn observe_request(&self, method: &str, route: &str, status: &str, duration: Duration) {
+        self.http_requests
+            .with_label_values(&[method, route, status])
+            .inc();
+
+        self.http_request_duration
+            .with_label_values(&[method, route, status])
+            .observe(duration.as_secs_f64());
+    }
+
+    // fn observe_query() records a statement that was run, the outcome is ok, not_found or error.
+    pub fn observe_query(&self, query: &str, outcome: &str, duration: Duration) {
+        self.db_query_duration
+            .with_label_values(&[query, outcome])
+            .observe(duration.as_secs_f64());
+    }
+
+    // fn observe_pool() records the current size of the primary database pool, this is called before rendering.
+    pub fn observe_pool(&self, db: &PgPool) {
+        self.db_pool_size.set(db.size() as i64);
+        self.db_pool_idle.set(db.num_idle() as i64);
+    }
+
+    // fn render() returns every metric in the Prometheus text format.
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = vec![];
+
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            return Err(err);
+        }
+
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+// InFlight increments a gauge while it is held, and decrements it once dropped, so the gauge stays correct when a
+// request is cancelled part way through.
+pub struct InFlight {
+    gauge: IntGauge,
+}
+
+// fn track() increments the gauge until the returned InFlight is dropped.
+pub fn track(gauge: &IntGauge) -> InFlight {
+    gauge.inc();
+
+    InFlight {
+        gauge: gauge.clone(),
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
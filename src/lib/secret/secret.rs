@@ -0,0 +1,153 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{cell::Cell, fmt};
+
+// What a secret is replaced with whenever it is serialised or debug printed.
+pub const REDACTED: &str = "***";
+
+thread_local! {
+    // Set while exposed() runs, so secrets serialise as their real value.
+    static EXPOSED: Cell<bool> = const { Cell::new(false) };
+}
+
+// Secret holds a credential, like a password, so it is not logged by accident. It serialises as "***" (outside of
+// exposed()), debug prints as Secret("***"), and does not implement Display, so the value can only be read through
+// expose().
+// Deserialising reads the real value, so secrets can still be loaded from config.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    // fn expose() returns the value, only call this where the value is actually used, like opening a connection.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    // fn into_inner() returns the value, consuming the secret.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl From<&str> for Secret<String> {
+    fn from(value: &str) -> Self {
+        Secret(value.to_string())
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({:?})", REDACTED)
+    }
+}
+
+impl<T: Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match EXPOSED.with(Cell::get) {
+            true => self.0.serialize(serializer),
+            false => serializer.serialize_str(REDACTED),
+        }
+    }
+}
+
+// fn exposed() runs the function with secrets serialising as their real value, on this thread only. This is only
+// meant for copying settings, like merging the defaults with the layers of config, never for logging.
+pub fn exposed<R>(function: impl FnOnce() -> R) -> R {
+    let _restore = Restore(EXPOSED.with(|exposed| exposed.replace(true)));
+
+    function()
+}
+
+// Restore sets the flag back when dropped, so a panic within exposed() can not leave secrets exposed on the thread.
+struct Restore(bool);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        EXPOSED.with(|exposed| exposed.set(self.0));
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+
+    #[derive(Serialize)]
+    struct Settings {
+        user: String,
+        password: Secret<String>,
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            user: String::from("postgres"),
+            password: Secret::from("hunter2"),
+        }
+    }
+
+    #[test]
+    fn debug_is_redacted() {
+        let secret: Secret<String> = Secret::from("hunter2");
+
+        assert_eq!(format!("{:?}", secret), "Secret(\"***\")");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn serialise_is_redacted() {
+        let json = serde_json::to_value(settings()).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({"user": "postgres", "password": "***"})
+        );
+    }
+
+    #[test]
+    fn exposed_serialises_the_value() {
+        let json = exposed(|| serde_json::to_value(settings())).unwrap();
+        assert_eq!(json["password"], "hunter2");
+
+        // Nested calls keep the secrets exposed until the outer call returns.
+        let json = exposed(|| {
+            exposed(|| ());
+            serde_json::to_value(settings())
+        })
+        .unwrap();
+        assert_eq!(json["password"], "hunter2");
+
+        // Outside of exposed() the secrets are redacted again.
+        assert_eq!(serde_json::to_value(settings()).unwrap()["password"], "***");
+    }
+
+    #[test]
+    fn exposed_is_cleared_after_a_panic() {
+        let result = panic::catch_unwind(|| exposed(|| panic!("while exposed")));
+        assert!(result.is_err());
+
+        assert!(!EXPOSED.with(Cell::get));
+        assert_eq!(serde_json::to_value(settings()).unwrap()["password"], "***");
+    }
+
+    #[test]
+    fn deserialise_reads_the_value() {
+        let secret: Secret<String> = serde_json::from_str("\"hunter2\"").unwrap();
+
+        assert_eq!(secret.into_inner(), "hunter2");
+    }
+}
@@ -0,0 +1,196 @@
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::DefaultBodyLimit,
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError, Router,
+};
+use hyper::server::Builder;
+use std::time::Duration;
+use tower::{
+    limit::GlobalConcurrencyLimitLayer, load_shed::error::Overloaded, load_shed::LoadShedLayer,
+    timeout::error::Elapsed, timeout::TimeoutLayer, ServiceBuilder,
+};
+
+// Limits protect the server from slow, large or too many requests, and set which protocols are served.
+#[derive(Clone)]
+pub struct Config {
+    // How long a request has to complete before a 503 is returned.
+    pub request_timeout: Duration,
+    // How long a client has to send the request headers before the connection is closed.
+    pub header_read_timeout: Duration,
+    // Whether connections are kept open between requests.
+    pub keep_alive: bool,
+    // The largest request body accepted, larger bodies are rejected with a 413.
+    pub max_body_size: usize,
+    // The number of requests handled at once, further requests are shed with a 503. 0 means no limit.
+    pub max_concurrent_requests: usize,
+    // The Retry-After header returned with a 503 when requests are shed, or take too long.
+    pub retry_after: Duration,
+    pub http2_enabled: bool,
+    // When true, HTTP/1 is not served at all.
+    pub http2_only: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            request_timeout: Duration::from_secs(30),
+            header_read_timeout: Duration::from_secs(10),
+            keep_alive: true,
+            max_body_size: 2 * 1024 * 1024,
+            max_concurrent_requests: 512,
+            retry_after: Duration::from_secs(5),
+            http2_enabled: true,
+            http2_only: false,
+        }
+    }
+}
+
+// fn apply() wraps every route with the request timeout, body limit, and concurrency limit. The router clones each
+// layer onto every route, so the concurrency limit uses one semaphore shared by all of them, otherwise the limit would
+// apply to each route on its own.
+pub fn apply(router: Router, config: &Config) -> Router {
+    let retry_after = config.retry_after;
+
+    let router = router.layer(DefaultBodyLimit::max(config.max_body_size));
+
+    let router = match config.max_concurrent_requests {
+        0 => router,
+        max_concurrent_requests => router.layer(
+            // Requests over the limit are shed straight away rather than queued, so clients can retry elsewhere.
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(move |err: BoxError| async move {
+                    handle_error(err, retry_after)
+                }))
+                .layer(LoadShedLayer::new())
+                .layer(GlobalConcurrencyLimitLayer::new(max_concurrent_requests)),
+        ),
+    };
+
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(move |err: BoxError| async move {
+                handle_error(err, retry_after)
+            }))
+            .layer(TimeoutLayer::new(config.request_timeout)),
+    )
+}
+
+// fn configure() applies the connection level limits, and protocol toggles to the hyper server.
+pub fn configure<I>(builder: Builder<I>, config: &Config) -> Builder<I> {
+    builder
+        .http1_header_read_timeout(config.header_read_timeout)
+        .http1_keepalive(config.keep_alive)
+        .http1_only(!config.http2_enabled)
+        .http2_only(config.http2_enabled && config.http2_only)
+}
+
+// fn handle_error() maps the errors from the limit layers to a response. A request that took too long is answered the
+// same as one that was shed, as the client did nothing wrong, and the server is likely too busy to answer in time.
+fn handle_error(err: BoxError, retry_after: Duration) -> Response {
+    let message = match err {
+        err if err.is::<Overloaded>() => {
+            "the server is handling too many requests, try again later"
+        }
+        err if err.is::<Elapsed>() => "the request took too long, try again later",
+        err => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("unhandled internal error : {}", err),
+            )
+                .into_response()
+        }
+    };
+
+    let mut response = (StatusCode::SERVICE_UNAVAILABLE, message).into_response();
+
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(retry_after.as_secs()),
+    );
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get};
+    use std::sync::Arc;
+    use tokio::sync::{Notify, Semaphore};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn concurrency_limit_is_shared_between_routes() {
+        // The first request holds on to its slot until it is released.
+        let entered = Arc::new(Notify::new());
+        let release = Arc::new(Semaphore::new(0));
+
+        let router = Router::new()
+            .route(
+                "/a",
+                get({
+                    let entered = entered.clone();
+                    let release = release.clone();
+                    move || async move {
+                        entered.notify_one();
+                        release.acquire().await.unwrap().forget();
+                    }
+                }),
+            )
+            .route("/b", get(|| async {}));
+
+        let config = Config {
+            max_concurrent_requests: 1,
+            ..Config::default()
+        };
+        let router = apply(router, &config);
+
+        let first = tokio::spawn(
+            router
+                .clone()
+                .oneshot(Request::get("/a").body(Body::empty()).unwrap()),
+        );
+        entered.notified().await;
+
+        let second = router
+            .clone()
+            .oneshot(Request::get("/b").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(second.headers()[header::RETRY_AFTER], "5");
+
+        release.add_permits(1);
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+
+        // Once the first request has finished, its slot is free again.
+        let third = router
+            .oneshot(Request::get("/b").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(third.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_are_unavailable() {
+        let router =
+            Router::new().route("/slow", get(|| tokio::time::sleep(Duration::from_secs(60))));
+
+        let config = Config {
+            request_timeout: Duration::from_millis(10),
+            ..Config::default()
+        };
+
+        let response = apply(router, &config)
+            .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+    }
+}
@@ -0,0 +1,319 @@
+use super::server::{ConnectionInfo, ServerError};
+use axum::extract::connect_info::Connected;
+use hyper::server::accept::Accept;
+use std::{
+    collections::BTreeSet,
+    env, fs, io,
+    net::{IpAddr, SocketAddr},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        io::{FromRawFd, IntoRawFd, RawFd},
+        net,
+    },
+    path::PathBuf,
+    pin::Pin,
+    process,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    task::{Context, Poll},
+};
+use tokio::net::{UnixListener, UnixStream};
+
+// The prefix of an address that listens on a unix socket, for example unix:/run/external-api.sock
+pub const UNIX_PREFIX: &str = "unix:";
+// The prefix of an address that uses a socket passed in by systemd, for example fd:0 is the first socket.
+pub const FD_PREFIX: &str = "fd:";
+// The first file descriptor passed in by systemd, as 0, 1, and 2 are stdin, stdout and stderr.
+const LISTEN_FDS_START: RawFd = 3;
+
+// A listener that has been bound, ready to be served.
+pub enum Listener {
+    Tcp(std::net::TcpListener),
+    // The path is only set when we created the socket, so it is removed once we stop serving.
+    Unix(net::UnixListener, Option<PathBuf>),
+}
+
+// fn bind() binds the address, which is either an ip address with the port, a unix socket path prefixed with unix:,
+// or a socket inherited from systemd prefixed with fd:. The mode sets the permissions of a new unix socket.
+pub fn bind(address: &str, port: u16, mode: Option<u32>) -> Result<Listener, ServerError> {
+    if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+        return bind_unix(address, PathBuf::from(path), mode);
+    }
+
+    if let Some(index) = address.strip_prefix(FD_PREFIX) {
+        return inherit(address, index);
+    }
+
+    // Attempt to parse string of loopback address to u8.
+    let host = match IpAddr::from_str(address) {
+        Ok(host) => host,
+        Err(err) => return Err(ServerError::Address(address.to_string(), err.to_string())),
+    };
+
+    // Create a new socket.
+    match bind_tcp(SocketAddr::new(host, port)) {
+        Ok(listener) => Ok(Listener::Tcp(listener)),
+        Err(err) => Err(err),
+    }
+}
+
+// fn bind_tcp() binds a tcp listener to the socket address.
+pub fn bind_tcp(socket_address: SocketAddr) -> Result<std::net::TcpListener, ServerError> {
+    let listener = match std::net::TcpListener::bind(socket_address) {
+        Ok(listener) => listener,
+        Err(err) => return Err(ServerError::Bind(socket_address.to_string(), err)),
+    };
+
+    // Tokio requires the listener to be non blocking.
+    if let Err(err) = listener.set_nonblocking(true) {
+        return Err(ServerError::Bind(socket_address.to_string(), err));
+    }
+
+    Ok(listener)
+}
+
+fn bind_unix(address: &str, path: PathBuf, mode: Option<u32>) -> Result<Listener, ServerError> {
+    // A socket left behind by a previous run would stop us binding, but we never remove anything else.
+    if let Ok(metadata) = fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            return Err(ServerError::Address(
+                address.to_string(),
+                String::from("the path exists and is not a socket"),
+            ));
+        }
+
+        // The socket is only stale when nothing is listening on it, otherwise another instance is still serving.
+        match net::UnixStream::connect(&path) {
+            Ok(_) => {
+                return Err(ServerError::Bind(
+                    address.to_string(),
+                    io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        "another process is listening on the socket",
+                    ),
+                ))
+            }
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {}
+            Err(err) => return Err(ServerError::Bind(address.to_string(), err)),
+        }
+
+        if let Err(err) = fs::remove_file(&path) {
+            return Err(ServerError::Bind(address.to_string(), err));
+        }
+    }
+
+    let listener = match net::UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => return Err(ServerError::Bind(address.to_string(), err)),
+    };
+
+    if let Some(mode) = mode {
+        if let Err(err) = fs::set_permissions(&path, fs::Permissions::from_mode(mode)) {
+            return Err(ServerError::Bind(address.to_string(), err));
+        }
+    }
+
+    if let Err(err) = listener.set_nonblocking(true) {
+        return Err(ServerError::Bind(address.to_string(), err));
+    }
+
+    Ok(Listener::Unix(listener, Some(path)))
+}
+
+// fn inherit() takes a socket passed in by systemd socket activation, using the LISTEN_PID and LISTEN_FDS
+// environment variables (see sd_listen_fds). The socket can either be tcp or unix, and each can only be taken once.
+fn inherit(address: &str, index: &str) -> Result<Listener, ServerError> {
+    let invalid = |reason: &str| ServerError::Address(address.to_string(), reason.to_string());
+
+    let index = match index.parse::<RawFd>() {
+        Ok(index) => index,
+        Err(_) => return Err(invalid("the file descriptor index is not a number")),
+    };
+
+    let activation = ACTIVATION.get_or_init(|| Mutex::new(Activation::from_env()));
+
+    let fd = match activation.lock() {
+        Ok(mut activation) => activation.claim(index),
+        Err(poisoned) => poisoned.into_inner().claim(index),
+    };
+
+    let fd = match fd {
+        Ok(fd) => fd,
+        Err(reason) => return Err(invalid(&reason)),
+    };
+
+    // We check which kind of socket we were given by asking for its local address as a unix socket, which fails when
+    // the socket is not a unix socket. systemd has already given us ownership of the file descriptor.
+    let listener = unsafe { net::UnixListener::from_raw_fd(fd) };
+
+    let listener = match listener.local_addr() {
+        Ok(_) => Listener::Unix(listener, None),
+        Err(_) => {
+            Listener::Tcp(unsafe { std::net::TcpListener::from_raw_fd(listener.into_raw_fd()) })
+        }
+    };
+
+    let nonblocking = match &listener {
+        Listener::Tcp(listener) => listener.set_nonblocking(true),
+        Listener::Unix(listener, _) => listener.set_nonblocking(true),
+    };
+
+    if let Err(err) = nonblocking {
+        return Err(ServerError::Bind(address.to_string(), err));
+    }
+
+    Ok(listener)
+}
+
+// Activation holds the sockets passed in by systemd. The environment is read once, and then cleared, so any child
+// process we start does not also take the sockets.
+struct Activation {
+    // The number of sockets passed in, or none when they were not meant for this process.
+    count: Option<RawFd>,
+    // Each socket can only be owned by one listener, so those already taken are tracked.
+    claimed: BTreeSet<RawFd>,
+}
+
+static ACTIVATION: OnceLock<Mutex<Activation>> = OnceLock::new();
+
+impl Activation {
+    // fn from_env() reads, and then removes, the socket activation environment variables.
+    fn from_env() -> Self {
+        // The sockets are only meant for us when LISTEN_PID matches our process id.
+        let count = match (env::var("LISTEN_PID"), env::var("LISTEN_FDS")) {
+            (Ok(pid), Ok(count)) if pid.parse::<u32>() == Ok(process::id()) => {
+                count.parse::<RawFd>().ok()
+            }
+            _ => None,
+        };
+
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(name);
+        }
+
+        Activation {
+            count,
+            claimed: BTreeSet::new(),
+        }
+    }
+
+    // fn claim() returns the file descriptor of the socket at the index, as long as no other listener has it.
+    fn claim(&mut self, index: RawFd) -> Result<RawFd, String> {
+        let count = match self.count {
+            Some(count) => count,
+            None => {
+                return Err(String::from(
+                    "no sockets were passed to this process (LISTEN_PID, LISTEN_FDS)",
+                ))
+            }
+        };
+
+        if index < 0 || index >= count {
+            return Err(format!(
+                "only {} sockets were passed to this process",
+                count
+            ));
+        }
+
+        if !self.claimed.insert(index) {
+            return Err(format!("the socket fd:{} is already in use", index));
+        }
+
+        Ok(LISTEN_FDS_START + index)
+    }
+}
+
+// UnixIncoming passes the connections from a unix socket to hyper.
+pub struct UnixIncoming {
+    pub listener: UnixListener,
+}
+
+impl Accept for UnixIncoming {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.listener
+            .poll_accept(cx)
+            .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+    }
+}
+
+impl Connected<&UnixStream> for ConnectionInfo {
+    fn connect_info(_: &UnixStream) -> Self {
+        ConnectionInfo {
+            remote_addr: None,
+            client: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("{}-{}.sock", name, process::id()))
+    }
+
+    #[test]
+    fn bind_unix_replaces_a_stale_socket() {
+        let path = socket_path("stale");
+        let address = format!("{}{}", UNIX_PREFIX, path.display());
+
+        // Dropping the listener leaves the socket file behind, with nothing listening on it.
+        fs::remove_file(&path).ok();
+        drop(net::UnixListener::bind(&path).unwrap());
+
+        let listener = bind_unix(&address, path.clone(), None);
+        fs::remove_file(&path).ok();
+
+        assert!(matches!(listener, Ok(Listener::Unix(_, Some(_)))));
+    }
+
+    #[test]
+    fn bind_unix_refuses_a_socket_in_use() {
+        let path = socket_path("in-use");
+        let address = format!("{}{}", UNIX_PREFIX, path.display());
+
+        fs::remove_file(&path).ok();
+        let _running = net::UnixListener::bind(&path).unwrap();
+
+        let listener = bind_unix(&address, path.clone(), None);
+        let exists = path.exists();
+        fs::remove_file(&path).ok();
+
+        match listener {
+            Err(ServerError::Bind(_, err)) => assert_eq!(err.kind(), io::ErrorKind::AddrInUse),
+            _ => panic!("expected the socket to be in use"),
+        }
+        assert!(exists);
+    }
+
+    #[test]
+    fn activation_claims_each_socket_once() {
+        let mut activation = Activation {
+            count: Some(2),
+            claimed: BTreeSet::new(),
+        };
+
+        assert_eq!(activation.claim(1), Ok(LISTEN_FDS_START + 1));
+        assert_eq!(activation.claim(0), Ok(LISTEN_FDS_START));
+        assert!(activation.claim(1).unwrap_err().contains("already in use"));
+        assert!(activation.claim(2).unwrap_err().contains("only 2 sockets"));
+        assert!(activation.claim(-1).is_err());
+    }
+
+    #[test]
+    fn activation_without_sockets_claims_nothing() {
+        let mut activation = Activation {
+            count: None,
+            claimed: BTreeSet::new(),
+        };
+
+        assert!(activation.claim(0).unwrap_err().contains("LISTEN_FDS"));
+    }
+}
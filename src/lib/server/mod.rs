@@ -1 +1,5 @@
+pub mod limits;
+pub mod listener;
+pub mod registry;
 pub mod server;
+pub mod tls;
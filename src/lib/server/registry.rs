@@ -0,0 +1,167 @@
+use crate::lib::health::health::Status;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use serde::Serialize;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+// The probes a check can be registered for, these follow the kubernetes probes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Probe {
+    // Fails when the process should be restarted.
+    Liveness,
+    // Fails when the process should not be sent traffic.
+    Readiness,
+    // Fails until the process has finished starting.
+    Startup,
+}
+
+type Check = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Status> + Send>> + Send + Sync>;
+
+struct Registered {
+    probes: Vec<Probe>,
+    check: Check,
+}
+
+// Registry holds the named checks that components register, and serves them as /livez, /readyz and /startupz.
+#[derive(Clone)]
+pub struct Registry {
+    checks: Arc<RwLock<Vec<Registered>>>,
+    started: Arc<AtomicBool>,
+    shutting_down: Arc<AtomicBool>,
+}
+
+// The JSON response of a probe, with the status of every check.
+#[derive(Serialize)]
+pub struct Report {
+    pub healthy: bool,
+    pub checks: Vec<Status>,
+}
+
+// fn new() creates an empty registry, that has not yet started.
+pub fn new() -> Registry {
+    Registry {
+        checks: Arc::new(RwLock::new(vec![])),
+        started: Arc::new(AtomicBool::new(false)),
+        shutting_down: Arc::new(AtomicBool::new(false)),
+    }
+}
+
+impl Registry {
+    // fn register() adds a check to the given probes. The check is named by the status it returns, and handles its
+    // own retries and timeouts, for example with health::check().
+    pub fn register<F, Fut>(&self, probes: &[Probe], check: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Status> + Send + 'static,
+    {
+        let mut checks = match self.checks.write() {
+            Ok(checks) => checks,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        checks.push(Registered {
+            probes: probes.to_vec(),
+            check: Arc::new(move || Box::pin(check())),
+        });
+    }
+
+    // fn mark_started() is called once start up has finished, until then /startupz fails.
+    pub fn mark_started(&self) {
+        self.started.store(true, Ordering::SeqCst);
+    }
+
+    // fn begin_shutdown() makes /readyz fail, so no new traffic is sent while we drain.
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    // async fn run() runs every check registered for the probe at the same time.
+    pub async fn run(&self, probe: Probe) -> Report {
+        let mut statuses = vec![];
+
+        if probe == Probe::Startup && !self.started.load(Ordering::SeqCst) {
+            statuses.push(state_status("startup", "start up has not finished"));
+        }
+
+        if probe == Probe::Readiness && self.shutting_down.load(Ordering::SeqCst) {
+            statuses.push(state_status("shutdown", "the service is shutting down"));
+        }
+
+        let checks: Vec<Check> = {
+            let checks = match self.checks.read() {
+                Ok(checks) => checks,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+
+            checks
+                .iter()
+                .filter(|registered| registered.probes.contains(&probe))
+                .map(|registered| registered.check.clone())
+                .collect()
+        };
+
+        let handles: Vec<_> = checks
+            .into_iter()
+            .map(|check| tokio::spawn(check()))
+            .collect();
+
+        for handle in handles {
+            match handle.await {
+                Ok(status) => statuses.push(status),
+                Err(err) => statuses.push(state_status("check", &err.to_string())),
+            }
+        }
+
+        Report {
+            healthy: statuses.iter().all(|status| status.healthy),
+            checks: statuses,
+        }
+    }
+
+    // fn router() creates the /livez, /readyz and /startupz routes, a failing probe returns a 503.
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/livez", get(livez))
+            .route("/readyz", get(readyz))
+            .route("/startupz", get(startupz))
+            .with_state(self.clone())
+    }
+}
+
+async fn livez(State(registry): State<Registry>) -> impl IntoResponse {
+    to_response(registry.run(Probe::Liveness).await)
+}
+
+async fn readyz(State(registry): State<Registry>) -> impl IntoResponse {
+    to_response(registry.run(Probe::Readiness).await)
+}
+
+async fn startupz(State(registry): State<Registry>) -> impl IntoResponse {
+    to_response(registry.run(Probe::Startup).await)
+}
+
+fn to_response(report: Report) -> impl IntoResponse {
+    let status_code = match report.healthy {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status_code, Json(report))
+}
+
+// fn state_status() creates a failing status for the state of the registry itself.
+fn state_status(name: &str, error: &str) -> Status {
+    Status {
+        name: name.to_string(),
+        healthy: false,
+        latency_ms: 0,
+        attempts: 0,
+        error: Some(error.to_string()),
+    }
+}
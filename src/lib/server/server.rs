@@ -1,103 +1,444 @@
-use axum::Router;
-use hyper::Uri;
+use super::{
+    limits,
+    listener::{self, Listener, UnixIncoming},
+    tls::{self, ClientIdentity},
+};
+use crate::lib::health::health;
+use axum::{
+    extract::{connect_info::Connected, State},
+    http::{header, uri::Authority, HeaderMap, StatusCode},
+    response::Redirect,
+    Router,
+};
+use hyper::{server::conn::AddrStream, Uri};
 use std::{
     error::Error,
-    net::{IpAddr, SocketAddr},
+    fmt, fs,
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
     str::FromStr,
 };
-use tokio::sync::oneshot::Sender;
+use tokio::{
+    net::{TcpListener, UnixListener},
+    sync::{mpsc, oneshot::Sender, watch::Receiver},
+    task::JoinHandle,
+};
 
 #[derive(Clone)]
 // The main Axum struct.
 pub struct Axum {
     pub web_address: String,
     pub port: u16,
+    pub socket_mode: Option<u32>,
     pub router: Router,
+    pub tls: Option<tls::Config>,
+    pub limits: limits::Config,
 }
 
 // Configuration struct for our Axum.
 pub struct Config {
+    // Either an ip address, a unix socket path prefixed with unix:, or a socket passed in by systemd
+    // prefixed with fd:, for example fd:0 for the first socket. The port is only used for ip addresses.
     pub web_address: String,
     pub port: u16,
+    // The permissions of a new unix socket, for example 0o660.
+    pub socket_mode: Option<u32>,
     pub router: Router,
+    // When provided, the server only accepts HTTPS connections.
+    pub tls: Option<tls::Config>,
+    // Timeouts, body and concurrency limits, and protocol toggles.
+    pub limits: limits::Config,
+}
+
+// ConnectionInfo is available to every handler through the ConnectInfo<ConnectionInfo> extractor.
+// The remote address is not set for unix sockets, and the client identity is only set when serving over TLS
+// and the client presented a verified certificate.
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    pub remote_addr: Option<SocketAddr>,
+    pub client: Option<ClientIdentity>,
+}
+
+impl Connected<&AddrStream> for ConnectionInfo {
+    fn connect_info(target: &AddrStream) -> Self {
+        ConnectionInfo {
+            remote_addr: Some(target.remote_addr()),
+            client: None,
+        }
+    }
+}
+
+// ServerError is returned when a server cannot be started, or sent on the shutdown signal when it stops serving.
+#[derive(Debug)]
+pub enum ServerError {
+    // The address is not valid, the reason is given.
+    Address(String, String),
+    // The address could not be bound, for example when the port is already in use.
+    Bind(String, io::Error),
+    // The certificates could not be loaded.
+    Tls(Box<dyn Error + Send + Sync>),
+    // The server failed while serving requests.
+    Serve(String, hyper::Error),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Address(address, err) => {
+                write!(f, "invalid server address {} : {}", address, err)
+            }
+            ServerError::Bind(address, err) => write!(f, "could not bind to {} : {}", address, err),
+            ServerError::Tls(err) => write!(f, "could not load tls certificates : {}", err),
+            ServerError::Serve(address, err) => {
+                write!(f, "server on {} stopped unexpectedly : {}", address, err)
+            }
+        }
+    }
 }
 
+impl Error for ServerError {}
+
+// The future that serves requests until shutdown.
+type Serving = Pin<Box<dyn Future<Output = Result<(), ServerError>> + Send>>;
+
 // fn new() returns a new Axum struct.
 pub fn new(config: Config) -> Axum {
     Axum {
         web_address: config.web_address,
         port: config.port,
+        socket_mode: config.socket_mode,
         router: config.router,
+        tls: config.tls,
+        limits: config.limits,
     }
 }
 
 // Axum contains functionalities to run the server.
 impl Axum {
-    // aync fn run_server() starts the axum server, ready to listen to requests, and then handle based on the axum
-    // configuration provided.
-    pub fn run_sever(self, shutdown_signal: Sender<()>) -> Result<(), Box<dyn std::error::Error>> {
+    // aync fn run_server() binds the listeners, and then starts the axum server in a new task, ready to listen to
+    // requests, and then handle based on the axum configuration provided. Binding happens first, so an error such as
+    // the port already being in use is returned here, rather than after start up has finished.
+    // Once true is sent on the shutdown receiver, the server stops accepting new connections, and the returned handle
+    // completes when every in-flight request has finished. If the server fails while serving, the cause is sent on
+    // the shutdown signal.
+    pub fn run_sever(
+        self,
+        shutdown: Receiver<bool>,
+        shutdown_signal: Sender<ServerError>,
+    ) -> Result<JoinHandle<()>, ServerError> {
         // We want to initialise a tracer (This could be run in a seperate thread on a seperate server)
 
-        // Attempt to parse string of loopback address to u8.
-        let host = match IpAddr::from_str(&self.web_address) {
-            Ok(host) => host,
-            Err(err) => return Err(Box::new(err)),
+        // The listener is bound before anything is spawned.
+        let listener = match listener::bind(&self.web_address, self.port, self.socket_mode) {
+            Ok(listener) => listener,
+            Err(err) => return Err(err),
         };
 
-        // Create a new socket.
-        let socket_address = SocketAddr::new(host, self.port);
+        // Every route is wrapped with our limits, the connection level limits are applied when serving.
+        let router = limits::apply(self.router, &self.limits);
 
-        tokio::spawn(async move {
-            // Bind our socket with the provided socket address.
-            // We also then start serving the web server, this will then block the application from running.
-            // We also add a signal receiver that listens to a sender signal. Once that signal is received,
-            // We can then unblock the application to shutdown gracefully.
-            let serving =
-                axum::Server::bind(&socket_address).serve(self.router.into_make_service());
+        let serving = match self.tls {
+            Some(tls) => {
+                // The certificates are loaded before we start, so a bad certificate stops the application starting.
+                let acceptor = match tls::new_acceptor(tls, self.limits.http2_enabled) {
+                    Ok(acceptor) => acceptor,
+                    Err(err) => return Err(ServerError::Tls(err)),
+                };
+                serve_tls(
+                    listener,
+                    self.web_address,
+                    router,
+                    &self.limits,
+                    acceptor,
+                    shutdown,
+                )
+            }
+            None => serve(listener, self.web_address, router, &self.limits, shutdown),
+        };
+
+        let serving = match serving {
+            Ok(serving) => serving,
+            Err(err) => return Err(err),
+        };
 
+        let handle = tokio::spawn(async move {
             // Here we just wait for the blocked application to either receive a signal, or an error that requires the server to exit.
-            // This allows us to atleast propergate the error the call stack.
-            if let Err(_) = serving.await {
-                shutdown_signal.send(()).ok();
+            // This allows us to propagate the real cause up the call stack.
+            if let Err(err) = serving.await {
+                shutdown_signal.send(err).ok();
             };
         });
 
-        Ok(())
+        Ok(handle)
     }
 }
 
-// async fn liveness_check() does a ping to the server to validate is liveness.
-pub async fn liveness_check(
+// async fn wait_for_shutdown() completes once true is sent on the shutdown receiver.
+// A closed channel means the sender is gone, so we also treat that as a shutdown.
+pub async fn wait_for_shutdown(mut shutdown: Receiver<bool>) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+// fn serve() returns the future that serves plain HTTP on the listener.
+fn serve(
+    listener: Listener,
     address: String,
-    port: u16,
-    max_attempts: u8,
-) -> Result<(), Box<dyn Error>> {
-    // We use hyper as the client to send requests for now.
-    let client = hyper::client::Client::new();
+    router: Router,
+    limits: &limits::Config,
+    shutdown: Receiver<bool>,
+) -> Result<Serving, ServerError> {
+    let make_service = router.into_make_service_with_connect_info::<ConnectionInfo>();
 
-    for i in 1..=max_attempts {
-        // Merge host and port.
-        let full_address = match Uri::from_str(format!("{}:{}", address, port.to_string()).as_str())
-        {
-            Ok(full_address) => full_address,
-            Err(err) => return Err(Box::new(err)),
-        };
+    match listener {
+        Listener::Tcp(listener) => {
+            let server = match axum::Server::from_tcp(listener) {
+                Ok(server) => server,
+                Err(err) => return Err(ServerError::Serve(address, err)),
+            };
 
-        let req = client.get(full_address);
+            let serving = limits::configure(server, limits)
+                .serve(make_service)
+                .with_graceful_shutdown(wait_for_shutdown(shutdown));
 
-        // Based on the number of attempts provided, we keep pinging the server until this limit is reached
-        // Once it has, we return an error.
-        match req.await {
-            Ok(_) => {
-                break;
-            }
-            Err(err) => {
-                if i == max_attempts {
-                    return Err(Box::new(err));
+            Ok(Box::pin(async move {
+                match serving.await {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(ServerError::Serve(address, err)),
                 }
+            }))
+        }
+        Listener::Unix(listener, path) => {
+            let listener = match UnixListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(err) => return Err(ServerError::Bind(address, err)),
+            };
+
+            let serving =
+                limits::configure(axum::Server::builder(UnixIncoming { listener }), limits)
+                    .serve(make_service)
+                    .with_graceful_shutdown(wait_for_shutdown(shutdown));
+
+            Ok(Box::pin(async move {
+                let serving = serving.await;
+
+                // We remove the socket we created, so the path can be bound again.
+                if let Some(path) = path {
+                    fs::remove_file(path).ok();
+                }
+
+                match serving {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(ServerError::Serve(address, err)),
+                }
+            }))
+        }
+    }
+}
+
+// fn serve_tls() returns the future that serves HTTPS on the listener, while watching the certificates for
+// changes. If configured, a plain HTTP listener is also bound, that redirects every request to HTTPS.
+fn serve_tls(
+    listener: Listener,
+    address: String,
+    router: Router,
+    limits: &limits::Config,
+    acceptor: tls::Acceptor,
+    shutdown: Receiver<bool>,
+) -> Result<Serving, ServerError> {
+    let listener = match listener {
+        Listener::Tcp(listener) => listener,
+        Listener::Unix(..) => {
+            return Err(ServerError::Address(
+                address,
+                String::from("tls is not supported on unix sockets"),
+            ))
+        }
+    };
+
+    let listener = match TcpListener::from_std(listener) {
+        Ok(listener) => listener,
+        Err(err) => return Err(ServerError::Bind(address, err)),
+    };
+
+    // The redirect listener uses the same ip address, as the address could have been inherited.
+    let socket_address = match listener.local_addr() {
+        Ok(socket_address) => socket_address,
+        Err(err) => return Err(ServerError::Bind(address, err)),
+    };
+
+    let redirecting = match acceptor.redirect_port() {
+        Some(redirect_port) => {
+            let redirect_address = SocketAddr::new(socket_address.ip(), redirect_port);
+
+            let redirect_listener = match listener::bind_tcp(redirect_address) {
+                Ok(redirect_listener) => redirect_listener,
+                Err(err) => return Err(err),
+            };
+
+            let redirect_router = Router::new()
+                .fallback(redirect_to_https)
+                .with_state(socket_address.port());
+
+            match serve(
+                Listener::Tcp(redirect_listener),
+                redirect_address.to_string(),
+                redirect_router,
+                limits,
+                shutdown.clone(),
+            ) {
+                Ok(redirecting) => Some(redirecting),
+                Err(err) => return Err(err),
+            }
+        }
+        None => None,
+    };
+
+    let (connections_send, connections_recv) = mpsc::channel(128);
+
+    tokio::spawn(acceptor.clone().watch(shutdown.clone()));
+    tokio::spawn(acceptor.accept(listener, connections_send, shutdown.clone()));
+
+    let server = axum::Server::builder(tls::Incoming {
+        connections: connections_recv,
+    });
+
+    let serving = limits::configure(server, limits)
+        .serve(router.into_make_service_with_connect_info::<ConnectionInfo>())
+        .with_graceful_shutdown(wait_for_shutdown(shutdown));
+
+    Ok(Box::pin(async move {
+        let serving = async move {
+            match serving.await {
+                Ok(_) => Ok(()),
+                Err(err) => Err(ServerError::Serve(address, err)),
             }
         };
+
+        match redirecting {
+            Some(redirecting) => {
+                let (serving, redirecting) = tokio::join!(serving, redirecting);
+                serving.and(redirecting)
+            }
+            None => serving.await,
+        }
+    }))
+}
+
+// async fn redirect_to_https() permanently redirects the request to the same host and path over HTTPS.
+async fn redirect_to_https(
+    State(https_port): State<u16>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Result<Redirect, StatusCode> {
+    let host = match headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| Authority::from_str(host).ok())
+    {
+        Some(authority) => authority.host().to_string(),
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    let location = match https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+
+    Ok(Redirect::permanent(&location))
+}
+
+// async fn liveness_check() does a ping to the server to validate is liveness, retrying with backoff as configured.
+// The address and port are the ones the server was configured with, an unspecified address like 0.0.0.0 is reached
+// through loopback. Any HTTP response means the server is alive. A server using TLS, or a unix socket, is alive when
+// it accepts the connection, as our client only speaks plain HTTP over tcp.
+pub async fn liveness_check(
+    address: &str,
+    port: u16,
+    tls: bool,
+    config: &health::Config,
+) -> health::Status {
+    if let Some(path) = address.strip_prefix(listener::UNIX_PREFIX) {
+        return health::check("web", config, || async {
+            tokio::net::UnixStream::connect(path).await?;
+            Ok(())
+        })
+        .await;
     }
 
-    Ok(())
+    let socket_address = match self_address(address, port) {
+        Ok(socket_address) => socket_address,
+        Err(err) => {
+            return health::Status {
+                name: String::from("web"),
+                healthy: false,
+                latency_ms: 0,
+                attempts: 0,
+                error: Some(format!("invalid address : {}", err)),
+            }
+        }
+    };
+
+    if tls {
+        return health::check("web", config, || async {
+            tokio::net::TcpStream::connect(socket_address).await?;
+            Ok(())
+        })
+        .await;
+    }
+
+    // We use hyper as the client to send requests for now.
+    let client = hyper::client::Client::new();
+
+    // Merge host and port.
+    let full_address = match Uri::from_str(format!("http://{}", socket_address).as_str()) {
+        Ok(full_address) => full_address,
+        Err(err) => {
+            return health::Status {
+                name: String::from("web"),
+                healthy: false,
+                latency_ms: 0,
+                attempts: 0,
+                error: Some(format!("invalid address : {}", err)),
+            }
+        }
+    };
+
+    health::check("web", config, || async {
+        client.get(full_address.clone()).await?;
+        Ok(())
+    })
+    .await
+}
+
+// fn self_address() returns the address to reach a server listening on the address from the same host.
+fn self_address(address: &str, port: u16) -> Result<SocketAddr, String> {
+    if address.starts_with(listener::FD_PREFIX) {
+        return Err(String::from(
+            "the address of a socket passed in by systemd is not known",
+        ));
+    }
+
+    let host = match IpAddr::from_str(address) {
+        Ok(host) => host,
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let host = match host {
+        IpAddr::V4(host) if host.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(host) if host.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        host => host,
+    };
+
+    Ok(SocketAddr::new(host, port))
 }
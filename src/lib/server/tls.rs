@@ -0,0 +1,331 @@
+use super::server::{wait_for_shutdown, ConnectionInfo};
+use crate::lib::logger::logger::Logger;
+use axum::extract::connect_info::Connected;
+use hyper::server::accept::Accept;
+use openssl::{
+    nid::Nid,
+    ssl::{self, AlpnError, Ssl, SslAcceptor, SslFiletype, SslMethod, SslVerifyMode},
+    x509::X509,
+};
+use std::{
+    error::Error,
+    fs, io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch::Receiver},
+    time::{interval, sleep, timeout},
+};
+use tokio_openssl::SslStream;
+
+// The number of seconds a client has to complete the TLS handshake, so slow clients cannot hold connections open.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// How long we wait before accepting again after an error, such as running out of file descriptors, so the accept
+// loop does not spin while the error persists.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+// Configuration to serve over TLS. Certificates are read from disk, and reloaded whenever the files change.
+#[derive(Clone)]
+pub struct Config {
+    pub cert_path: String,
+    pub key_path: String,
+    // When provided, clients are asked for a certificate signed by this CA (mTLS).
+    pub client_ca_path: Option<String>,
+    // When true, clients without a valid certificate are rejected during the handshake.
+    pub client_auth_required: bool,
+    // When provided, a plain HTTP listener on this port redirects every request to HTTPS.
+    pub redirect_port: Option<u16>,
+    // How often the certificate files are checked for changes.
+    pub reload_interval: Duration,
+    // Where failed reloads, and failures to accept connections are logged.
+    pub logger: Logger,
+}
+
+// The identity of a client that presented a verified certificate.
+#[derive(Clone, Debug)]
+pub struct ClientIdentity {
+    pub subject: String,
+    pub common_name: Option<String>,
+}
+
+// Acceptor holds the current TLS configuration, which is swapped out when the certificates are reloaded.
+#[derive(Clone)]
+pub struct Acceptor {
+    config: Config,
+    http2: bool,
+    current: Arc<RwLock<SslAcceptor>>,
+}
+
+// fn new_acceptor() loads the certificates, an error is returned if they cannot be used.
+// When http2 is enabled, it is offered to clients during the handshake (ALPN).
+pub fn new_acceptor(config: Config, http2: bool) -> Result<Acceptor, Box<dyn Error + Send + Sync>> {
+    let acceptor = match build_acceptor(&config, http2) {
+        Ok(acceptor) => acceptor,
+        Err(err) => return Err(err),
+    };
+
+    Ok(Acceptor {
+        config,
+        http2,
+        current: Arc::new(RwLock::new(acceptor)),
+    })
+}
+
+impl Acceptor {
+    // async fn watch() reloads the certificates whenever the files change, until shutdown. If the new certificates
+    // cannot be loaded, the current ones are kept.
+    pub async fn watch(self, mut shutdown: Receiver<bool>) {
+        let mut modified = self.modified();
+        let mut ticker = interval(self.config.reload_interval);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {},
+                _ = shutdown.changed() => {
+                    if *shutdown.borrow() {
+                        return;
+                    }
+                    continue;
+                },
+            };
+
+            let latest = self.modified();
+            if latest == modified {
+                continue;
+            }
+            modified = latest;
+
+            match build_acceptor(&self.config, self.http2) {
+                Ok(acceptor) => {
+                    let mut current = match self.current.write() {
+                        Ok(current) => current,
+                        Err(poisoned) => poisoned.into_inner(),
+                    };
+                    *current = acceptor;
+                }
+                Err(err) => self.config.logger.warn_w(
+                    format!(
+                        "could not reload tls certificates, keeping the current ones : {}",
+                        err
+                    )
+                    .as_str(),
+                    Some("TLS"),
+                ),
+            }
+        }
+    }
+
+    // async fn accept() accepts new connections, and completes each handshake in its own task so a slow client does
+    // not block the others. Completed connections are sent to the server through the channel.
+    pub async fn accept(
+        self,
+        listener: TcpListener,
+        connections: mpsc::Sender<TlsStream>,
+        shutdown: Receiver<bool>,
+    ) {
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    // The client went away before we accepted, there is nothing wrong with the listener.
+                    Err(err) if is_connection_error(&err) => continue,
+                    Err(err) => {
+                        self.config.logger.error_w(
+                            format!("could not accept connection : {}", err).as_str(),
+                            Some("TLS"),
+                        );
+                        sleep(ACCEPT_ERROR_DELAY).await;
+                        continue;
+                    }
+                },
+                _ = wait_for_shutdown(shutdown.clone()) => return,
+            };
+
+            let acceptor = match self.current.read() {
+                Ok(current) => current.clone(),
+                Err(poisoned) => poisoned.into_inner().clone(),
+            };
+
+            let connections = connections.clone();
+
+            tokio::spawn(async move {
+                if let Ok(Ok(stream)) =
+                    timeout(HANDSHAKE_TIMEOUT, handshake(acceptor, stream, remote_addr)).await
+                {
+                    connections.send(stream).await.ok();
+                }
+            });
+        }
+    }
+
+    // fn redirect_port() returns the port to redirect plain HTTP requests from, if configured.
+    pub fn redirect_port(&self) -> Option<u16> {
+        self.config.redirect_port
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        let mut paths = vec![&self.config.cert_path, &self.config.key_path];
+        if let Some(client_ca_path) = &self.config.client_ca_path {
+            paths.push(client_ca_path);
+        }
+
+        paths
+            .into_iter()
+            .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+            .collect()
+    }
+}
+
+// fn build_acceptor() creates the openssl acceptor from the certificate files.
+fn build_acceptor(
+    config: &Config,
+    http2: bool,
+) -> Result<SslAcceptor, Box<dyn Error + Send + Sync>> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+
+    builder.set_private_key_file(&config.key_path, SslFiletype::PEM)?;
+    builder.set_certificate_chain_file(&config.cert_path)?;
+    builder.check_private_key()?;
+
+    if let Some(client_ca_path) = &config.client_ca_path {
+        builder.set_ca_file(client_ca_path)?;
+
+        let mut mode = SslVerifyMode::PEER;
+        if config.client_auth_required {
+            mode |= SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+        }
+        builder.set_verify(mode);
+    }
+
+    // We pick the first of our protocols the client supports, falling back to HTTP/1.1 when none match.
+    let protocols: &'static [u8] = match http2 {
+        true => b"\x02h2\x08http/1.1",
+        false => b"\x08http/1.1",
+    };
+    builder.set_alpn_select_callback(move |_, client| {
+        ssl::select_next_proto(protocols, client).ok_or(AlpnError::NOACK)
+    });
+
+    Ok(builder.build())
+}
+
+// fn handshake() completes the TLS handshake, and reads the identity from the client certificate if one was given.
+async fn handshake(
+    acceptor: SslAcceptor,
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+) -> Result<TlsStream, Box<dyn Error + Send + Sync>> {
+    let ssl = Ssl::new(acceptor.context())?;
+    let mut stream = SslStream::new(ssl, stream)?;
+
+    Pin::new(&mut stream).accept().await?;
+
+    let client = stream
+        .ssl()
+        .peer_certificate()
+        .map(|cert| client_identity(&cert));
+
+    Ok(TlsStream {
+        inner: stream,
+        remote_addr,
+        client,
+    })
+}
+
+fn client_identity(cert: &X509) -> ClientIdentity {
+    let mut subject = vec![];
+    let mut common_name = None;
+
+    for entry in cert.subject_name().entries() {
+        let value = String::from_utf8_lossy(entry.data().as_slice()).to_string();
+
+        if entry.object().nid() == Nid::COMMONNAME {
+            common_name = Some(value.clone());
+        }
+
+        let name = entry.object().nid().short_name().unwrap_or("UNKNOWN");
+        subject.push(format!("{}={}", name, value));
+    }
+
+    ClientIdentity {
+        subject: subject.join(", "),
+        common_name,
+    }
+}
+
+// A connection that has completed the TLS handshake.
+pub struct TlsStream {
+    inner: SslStream<TcpStream>,
+    remote_addr: SocketAddr,
+    client: Option<ClientIdentity>,
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Connected<&TlsStream> for ConnectionInfo {
+    fn connect_info(target: &TlsStream) -> Self {
+        ConnectionInfo {
+            remote_addr: Some(target.remote_addr),
+            client: target.client.clone(),
+        }
+    }
+}
+
+// Incoming passes the connections that have completed their handshake to hyper.
+pub struct Incoming {
+    pub connections: mpsc::Receiver<TlsStream>,
+}
+
+impl Accept for Incoming {
+    type Conn = TlsStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.connections.poll_recv(cx).map(|stream| stream.map(Ok))
+    }
+}
+
+// fn is_connection_error() checks if the error belongs to a single connection, rather than the listener.
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}